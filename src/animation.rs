@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+    reflect::TypePath,
+    tasks::futures_lite::AsyncReadExt,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+
+use crate::{aseprite::Aseprite, GameState};
+
+// ······
+// Plugin
+// ······
+
+pub struct SpriteAnimationPlugin;
+
+impl Plugin for SpriteAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<AnimationSet>()
+            .init_asset_loader::<AnimationSetLoader>()
+            .add_systems(
+                Update,
+                animate.run_if(in_state(GameState::Play).or_else(in_state(GameState::Editor))),
+            );
+    }
+}
+
+// ······
+// Assets
+// ······
+
+// Named animation clips, loaded from a `.anim.ron` file
+#[derive(Asset, TypePath, Deserialize)]
+pub struct AnimationSet {
+    // Aseprite file where the clip tags are looked up
+    #[serde(default)]
+    pub sprite: Option<String>,
+    pub clips: HashMap<String, Clip>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Clip {
+    // The frames are either taken from an aseprite tag or listed here
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub frames: Vec<usize>,
+    // Seconds for each frame, the last one is used for the rest
+    // Tag clips use the durations of the aseprite file if they are empty
+    #[serde(default)]
+    pub durations: Vec<f32>,
+    #[serde(default = "default_looping")]
+    pub looping: bool,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+}
+
+fn default_looping() -> bool {
+    true
+}
+
+fn default_speed() -> f32 {
+    1.
+}
+
+impl Clip {
+    fn duration(&self, frame: usize) -> f32 {
+        let duration = self
+            .durations
+            .get(frame)
+            .or(self.durations.last())
+            .copied()
+            .unwrap_or(0.1);
+        duration / self.speed.max(0.01)
+    }
+}
+
+#[derive(Default)]
+pub struct AnimationSetLoader;
+
+impl AssetLoader for AnimationSetLoader {
+    type Asset = AnimationSet;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<AnimationSet, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let mut set = ron::de::from_bytes::<AnimationSet>(&bytes)?;
+
+            // Fill the tag clips with the frames from the sprite
+            if let Some(path) = set.sprite.clone() {
+                let sprite = load_context.load_direct(path).await?;
+                let sprite = sprite
+                    .get::<Aseprite>()
+                    .ok_or("Animation sprites must be aseprite files")?;
+
+                for clip in set.clips.values_mut() {
+                    let Some(tag) = &clip.tag else {
+                        continue;
+                    };
+                    let from_tag = sprite.clip(tag);
+                    clip.frames = from_tag.frames;
+                    if clip.durations.is_empty() {
+                        clip.durations = from_tag.durations;
+                    }
+                }
+            }
+            Ok(set)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.ron"]
+    }
+}
+
+// ··········
+// Components
+// ··········
+
+// Plays the clips of an animation set, changing the sprite index if there is one
+#[derive(Component)]
+pub struct Animator {
+    set: Handle<AnimationSet>,
+    clip: String,
+    frame: usize,
+    timer: Timer,
+    started: bool,
+    finished: bool,
+    index: Option<usize>,
+}
+
+impl Animator {
+    pub fn new(set: Handle<AnimationSet>, clip: &str) -> Self {
+        Self {
+            set,
+            clip: clip.to_string(),
+            frame: 0,
+            timer: Timer::default(),
+            started: false,
+            finished: false,
+            index: None,
+        }
+    }
+
+    // Switch to another clip, it keeps playing if it is the current one
+    pub fn play(&mut self, clip: &str) {
+        if self.clip != clip {
+            self.clip = clip.to_string();
+            self.started = false;
+        }
+    }
+
+    pub fn clip(&self) -> &str {
+        &self.clip
+    }
+
+    // A clip that doesn't loop stays on its last frame when it ends
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // Atlas index of the current frame, if the clip has started
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+}
+
+// ·······
+// Systems
+// ·······
+
+fn animate(
+    time: Res<Time>,
+    sets: Res<Assets<AnimationSet>>,
+    mut animators: Query<(&mut Animator, Option<&mut TextureAtlasSprite>)>,
+) {
+    for (mut animator, sprite) in animators.iter_mut() {
+        let Some(clip) = sets
+            .get(&animator.set)
+            .and_then(|set| set.clips.get(&animator.clip))
+        else {
+            continue;
+        };
+        if clip.frames.is_empty() {
+            continue;
+        }
+
+        let changed = if !animator.started {
+            animator.started = true;
+            animator.finished = false;
+            animator.frame = 0;
+            true
+        } else if !animator.finished && animator.timer.tick(time.delta()).finished() {
+            if animator.frame + 1 < clip.frames.len() {
+                animator.frame += 1;
+                true
+            } else if clip.looping {
+                animator.frame = 0;
+                true
+            } else {
+                animator.finished = true;
+                false
+            }
+        } else {
+            false
+        };
+        if !changed {
+            continue;
+        }
+
+        let frame = animator.frame;
+        animator.timer = Timer::from_seconds(clip.duration(frame), TimerMode::Once);
+        animator.index = Some(clip.frames[frame]);
+        if let Some(mut sprite) = sprite {
+            sprite.index = clip.frames[frame];
+        }
+    }
+}
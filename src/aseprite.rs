@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use asefile::{AnimationDirection, AsepriteFile};
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+    reflect::TypePath,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::futures_lite::AsyncReadExt,
+    utils::BoxedFuture,
+};
+
+// ······
+// Assets
+// ······
+
+// Sprite sheet read straight from an `.aseprite` file
+// Frames are placed in a row, with all the visible layers
+#[derive(Asset, TypePath)]
+pub struct Aseprite {
+    pub atlas: Handle<TextureAtlas>,
+    pub clips: HashMap<String, SpriteClip>,
+}
+
+impl Aseprite {
+    pub fn clip(&self, name: &str) -> SpriteClip {
+        self.clips.get(name).cloned().unwrap_or_else(|| {
+            warn!("Missing aseprite tag {}", name);
+            SpriteClip::default()
+        })
+    }
+}
+
+// Frames of a tag in play order, with how many seconds each one is shown
+#[derive(Clone, Default)]
+pub struct SpriteClip {
+    pub frames: Vec<usize>,
+    pub durations: Vec<f32>,
+}
+
+#[derive(Default)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    type Asset = Aseprite;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Aseprite, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let ase = AsepriteFile::read(bytes.as_slice())?;
+            let size = UVec2::new(ase.width() as u32, ase.height() as u32);
+            let frames = ase.num_frames();
+
+            let image = sheet(size, (0..frames).map(|i| ase.frame(i).image().into_raw()));
+            let atlas = add_atlas(load_context, image, size, frames as usize);
+
+            // Each tag is a clip, with its frames ordered by the tag direction
+            let mut clips = HashMap::new();
+            for tag in (0..ase.num_tags()).map(|i| ase.tag(i)) {
+                let mut order = (tag.from_frame()..=tag.to_frame()).collect::<Vec<_>>();
+                match tag.animation_direction() {
+                    AnimationDirection::Reverse => order.reverse(),
+                    AnimationDirection::PingPong => {
+                        let back = order
+                            .iter()
+                            .rev()
+                            .skip(1)
+                            .take(order.len().saturating_sub(2))
+                            .copied()
+                            .collect::<Vec<_>>();
+                        order.extend(back);
+                    }
+                    _ => {}
+                }
+
+                clips.insert(
+                    tag.name().to_string(),
+                    SpriteClip {
+                        frames: order.iter().map(|i| *i as usize).collect(),
+                        durations: order
+                            .iter()
+                            .map(|i| ase.frame(*i).duration() as f32 / 1000.)
+                            .collect(),
+                    },
+                );
+            }
+
+            Ok(Aseprite { atlas, clips })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Place the frames next to each other in a single image
+fn sheet(size: UVec2, frames: impl ExactSizeIterator<Item = Vec<u8>>) -> Image {
+    let count = frames.len() as u32;
+    let row_len = size.x as usize * 4;
+    let mut data = vec![0; (size.x * count * size.y) as usize * 4];
+
+    for (i, frame) in frames.enumerate() {
+        for (y, row) in frame.chunks_exact(row_len).enumerate() {
+            let start = (y * count as usize + i) * row_len;
+            data[start..start + row_len].copy_from_slice(row);
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: size.x * count,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// Add a sheet as the labeled assets `image` and `atlas`
+fn add_atlas(
+    load_context: &mut LoadContext,
+    image: Image,
+    size: UVec2,
+    frames: usize,
+) -> Handle<TextureAtlas> {
+    let image = load_context.add_labeled_asset("image".to_string(), image);
+    let atlas = TextureAtlas::from_grid(image, size.as_vec2(), frames, 1, None, None);
+    load_context.add_labeled_asset("atlas".to_string(), atlas)
+}
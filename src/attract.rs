@@ -0,0 +1,136 @@
+#![allow(clippy::too_many_arguments)]
+
+use bevy::{
+    input::{gamepad::GamepadButtonInput, keyboard::KeyboardInput, mouse::MouseButtonInput},
+    prelude::*,
+};
+
+use crate::{
+    bot::Bot, game::GameMode, level::RestartRun, menu::MenuState, tilemap::NeedsReset, ui::UiNode,
+    GameState,
+};
+
+// Seconds without input on the main menu before the game starts playing itself
+const ATTRACT_DELAY: f32 = 20.;
+
+// ······
+// Plugin
+// ······
+
+// Demo run played by the bot behind the main menu when it is left alone
+pub struct AttractPlugin;
+
+impl Plugin for AttractPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            wait_idle.run_if(in_state(GameState::Menu).and_then(in_state(MenuState::Main))),
+        )
+        .add_systems(
+            Update,
+            leave_attract.run_if(in_state(GameState::Play).and_then(resource_exists::<Attract>())),
+        )
+        .add_systems(
+            OnExit(GameState::Play),
+            exit_attract.run_if(resource_exists::<Attract>()),
+        )
+        .add_systems(
+            OnEnter(GameState::Menu),
+            clear_attract.run_if(resource_exists::<Attract>()),
+        );
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// The current run is a demo, it ends with any input and doesn't count for the score
+#[derive(Resource)]
+pub struct Attract {
+    // If the bot was already playing before the demo
+    bot_active: bool,
+}
+
+// ·······
+// Systems
+// ·······
+
+// Start the demo after a while without input, unless there is a paused run
+fn wait_idle(
+    mut cmd: Commands,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut bot: ResMut<Bot>,
+    needs_reset: Option<Res<NeedsReset>>,
+    keyboard: EventReader<KeyboardInput>,
+    mouse: EventReader<MouseButtonInput>,
+    cursor: EventReader<CursorMoved>,
+    gamepad: EventReader<GamepadButtonInput>,
+) {
+    let timer = timer.get_or_insert_with(|| Timer::from_seconds(ATTRACT_DELAY, TimerMode::Once));
+
+    if any_input(keyboard, mouse, cursor, gamepad) || needs_reset.is_none() {
+        timer.reset();
+        return;
+    }
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    timer.reset();
+    cmd.insert_resource(Attract {
+        bot_active: bot.active,
+    });
+    cmd.insert_resource(GameMode::Endless);
+    bot.active = true;
+    game_state.set(GameState::Play);
+}
+
+// Any input goes back to the menu, ending the demo run
+fn leave_attract(
+    mut game_state: ResMut<NextState<GameState>>,
+    keyboard: EventReader<KeyboardInput>,
+    mouse: EventReader<MouseButtonInput>,
+    cursor: EventReader<CursorMoved>,
+    gamepad: EventReader<GamepadButtonInput>,
+) {
+    if any_input(keyboard, mouse, cursor, gamepad) {
+        game_state.set(GameState::End);
+    }
+}
+
+// Skip the end screen, both when leaving and when the bot loses
+// The menu that was left over the demo is built again when going back to it
+fn exit_attract(mut cmd: Commands, node: Query<Entity, With<UiNode>>) {
+    if let Ok(node) = node.get_single() {
+        if let Some(mut entity) = cmd.get_entity(node) {
+            entity.despawn_descendants();
+        }
+    }
+    cmd.insert_resource(RestartRun(GameState::Menu));
+}
+
+fn clear_attract(mut cmd: Commands, attract: Res<Attract>, mut bot: ResMut<Bot>) {
+    bot.active = attract.bot_active;
+    cmd.remove_resource::<Attract>();
+}
+
+// ·····
+// Extra
+// ·····
+
+fn any_input(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut mouse: EventReader<MouseButtonInput>,
+    mut cursor: EventReader<CursorMoved>,
+    mut gamepad: EventReader<GamepadButtonInput>,
+) -> bool {
+    // Read every event so they don't count again on the next frame
+    let keyboard = keyboard.read().count() > 0;
+    let mouse = mouse.read().count() > 0;
+    let cursor = cursor.read().count() > 0;
+    let gamepad = gamepad.read().count() > 0;
+    keyboard || mouse || cursor || gamepad
+}
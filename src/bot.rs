@@ -0,0 +1,544 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ecs_tilemap::prelude::*;
+use bevy_persistent::Persistent;
+
+use crate::{
+    config::Keybinds,
+    input::{Bind, InputSet, MousePosition},
+    tilemap::{
+        get_neighbours, tile_in_level, tile_to_pos, Affinity, EndTile, LevelSize, PathTile,
+        StartTile, Terrain, TilemapLayer, TilesAvailable, MAP_SIZE,
+    },
+    GameState,
+};
+
+// Seconds between each tile the bot places or erases
+const BOT_DELAY: f32 = 0.25;
+
+// ······
+// Plugin
+// ······
+
+// Player that connects the starts and ends by itself
+// It can be turned on for headless runs with the `CHARON_BOT` environment variable
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Bot {
+            active: std::env::var("CHARON_BOT").is_ok(),
+            ..default()
+        })
+        .add_systems(
+            PreUpdate,
+            (plan_moves, play_moves)
+                .chain()
+                .after(InputSet)
+                .run_if(in_state(GameState::Play).and_then(|bot: Res<Bot>| bot.active)),
+        )
+        .add_systems(OnExit(GameState::Play), stop_bot);
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+#[derive(Resource)]
+pub struct Bot {
+    pub active: bool,
+    moves: VecDeque<BotMove>,
+    phase: BotPhase,
+    timer: Timer,
+    // Plan again even if the tiles available didn't change
+    replan: bool,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self {
+            active: false,
+            moves: VecDeque::new(),
+            phase: BotPhase::Aim,
+            timer: Timer::from_seconds(BOT_DELAY, TimerMode::Once),
+            replan: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BotMove {
+    Build(TilePos),
+    Erase(TilePos),
+}
+
+impl BotMove {
+    pub fn pos(&self) -> TilePos {
+        match self {
+            BotMove::Build(pos) | BotMove::Erase(pos) => *pos,
+        }
+    }
+}
+
+// Each move is a click, moving the mouse over the tile, then pressing and releasing it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BotPhase {
+    Aim,
+    Press(BotMove),
+    Release,
+}
+
+// Snapshot of everything the solver needs to know about the level
+pub struct Board {
+    pub map_type: TilemapType,
+    pub level_size: LevelSize,
+    pub available: u32,
+    pub terrain: HashMap<TilePos, Terrain>,
+    // Starts with their affinity and if they already reach an end
+    pub starts: Vec<(TilePos, Option<Affinity>, bool)>,
+    pub ends: Vec<(TilePos, Option<Affinity>)>,
+    // Built rivers, with the ends they lead to
+    pub paths: HashMap<TilePos, BoardPath>,
+}
+
+pub struct BoardPath {
+    pub ends: Vec<TilePos>,
+    pub bridge: bool,
+    pub current: bool,
+}
+
+// Queries used to read the board
+#[derive(SystemParam)]
+pub struct BoardState<'w, 's> {
+    level_size: Option<Res<'w, LevelSize>>,
+    available: Option<Res<'w, TilesAvailable>>,
+    tilemap: Query<'w, 's, (&'static TilemapLayer, &'static TilemapType)>,
+    terrain: Query<'w, 's, (&'static TilePos, &'static Terrain)>,
+    starts: Query<
+        'w,
+        's,
+        (
+            &'static TilePos,
+            &'static StartTile,
+            Option<&'static Affinity>,
+        ),
+    >,
+    ends: Query<'w, 's, (&'static TilePos, Option<&'static Affinity>), With<EndTile>>,
+    paths: Query<'w, 's, (&'static TilePos, &'static PathTile)>,
+}
+
+impl BoardState<'_, '_> {
+    pub fn board(&self) -> Option<Board> {
+        let level_size = self.level_size.as_ref()?;
+        let available = self.available.as_ref()?;
+        let map_type = self
+            .tilemap
+            .iter()
+            .find(|(layer, _)| matches!(layer, TilemapLayer::RiverStix))
+            .map(|(_, map_type)| *map_type)?;
+
+        Some(Board {
+            map_type,
+            level_size: LevelSize(level_size.0),
+            available: available.0,
+            terrain: self
+                .terrain
+                .iter()
+                .map(|(pos, terrain)| (*pos, *terrain))
+                .collect(),
+            starts: self
+                .starts
+                .iter()
+                .map(|(pos, start, affinity)| (*pos, affinity.copied(), start.connected))
+                .collect(),
+            ends: self
+                .ends
+                .iter()
+                .map(|(pos, affinity)| (*pos, affinity.copied()))
+                .collect(),
+            paths: self
+                .paths
+                .iter()
+                .map(|(pos, path)| {
+                    let ends = path
+                        .distance
+                        .iter()
+                        .filter(|(_, dist)| dist.is_finite())
+                        .map(|(end, _)| *end)
+                        .collect();
+                    (
+                        *pos,
+                        BoardPath {
+                            ends,
+                            bridge: path.bridge,
+                            current: path.current.is_some(),
+                        },
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+// ·······
+// Systems
+// ·······
+
+// Look for new moves when the last plan is done and the board changed
+fn plan_moves(mut bot: ResMut<Bot>, available: Option<Res<TilesAvailable>>, board: BoardState) {
+    let changed = bot.replan || available.map_or(false, |available| available.is_changed());
+    if !changed || !bot.moves.is_empty() || bot.phase != BotPhase::Aim {
+        return;
+    }
+    if let Some(board) = board.board() {
+        bot.moves = solve(&board).into();
+        bot.replan = false;
+    }
+}
+
+// Click the tiles of each move using the same input as the player
+fn play_moves(
+    time: Res<Time>,
+    mut bot: ResMut<Bot>,
+    mut input: ResMut<Input<Bind>>,
+    mut mouse: ResMut<MousePosition>,
+    keybinds: Res<Persistent<Keybinds>>,
+    tilemap: Query<(
+        &TilemapLayer,
+        &TilemapGridSize,
+        &TilemapType,
+        &TileStorage,
+        &Transform,
+    )>,
+    paths: Query<&PathTile>,
+) {
+    let Some(bind) = keybinds.interact.first().copied() else {
+        return;
+    };
+    let Some((_, grid_size, map_type, storage, trans)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    match bot.phase {
+        BotPhase::Aim => {
+            if !bot.timer.tick(time.delta()).finished() {
+                return;
+            }
+            let Some(next) = bot.moves.pop_front() else {
+                return;
+            };
+
+            // The board may have changed since the plan was made, in that case plan again
+            let built = storage
+                .get(&next.pos())
+                .map_or(false, |entity| paths.contains(entity));
+            if built != matches!(next, BotMove::Erase(_)) {
+                bot.moves.clear();
+                bot.replan = true;
+                return;
+            }
+
+            mouse.0 = tile_to_pos(&next.pos(), grid_size, map_type, trans);
+            bot.phase = BotPhase::Press(next);
+        }
+        BotPhase::Press(next) => {
+            // Keep the mouse over the tile in case it was moved
+            mouse.0 = tile_to_pos(&next.pos(), grid_size, map_type, trans);
+            input.press(bind);
+            bot.phase = BotPhase::Release;
+        }
+        BotPhase::Release => {
+            input.release(bind);
+            bot.phase = BotPhase::Aim;
+            bot.timer.reset();
+        }
+    }
+}
+
+fn stop_bot(mut bot: ResMut<Bot>) {
+    bot.moves.clear();
+    bot.phase = BotPhase::Aim;
+    bot.replan = true;
+}
+
+// ·····
+// Extra
+// ·····
+
+// Moves that connect as many starts as possible with the tiles available
+// Rivers that lead nowhere are erased first, then the starts are connected one by one, always
+// picking the one closest to the network of rivers (a Steiner tree heuristic)
+pub fn solve(board: &Board) -> Vec<BotMove> {
+    let mut moves = Vec::new();
+    let mut budget = board.available;
+
+    let is_start = |pos: &TilePos| board.starts.iter().any(|(p, ..)| p == pos);
+    let is_end = |pos: &TilePos| board.ends.iter().any(|(p, _)| p == pos);
+
+    // Ends reachable from each tile, the planned rivers are added as they are chosen
+    let mut reaches: HashMap<TilePos, Vec<TilePos>> = board
+        .ends
+        .iter()
+        .map(|(pos, _)| (*pos, vec![*pos]))
+        .collect();
+    let mut built = HashSet::new();
+
+    for (pos, path) in board.paths.iter() {
+        if is_start(pos) || is_end(pos) {
+            continue;
+        }
+        if !path.ends.is_empty() {
+            reaches.insert(*pos, path.ends.clone());
+            built.insert(*pos);
+        } else if !path.bridge && !path.current {
+            // Get back the tiles of rivers that lead nowhere
+            moves.push(BotMove::Erase(*pos));
+            budget += Terrain::cost(board.terrain.get(pos)).unwrap_or(0);
+        } else {
+            built.insert(*pos);
+        }
+    }
+
+    let mut pending = board
+        .starts
+        .iter()
+        .filter(|(_, _, connected)| !connected)
+        .map(|(pos, affinity, _)| (*pos, *affinity))
+        .collect::<Vec<_>>();
+
+    while !pending.is_empty() {
+        // Route to the network from every start that is left, keeping the cheapest
+        let best = pending
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (pos, affinity))| {
+                route(board, pos, affinity.as_ref(), &reaches, &built)
+                    .map(|(cost, tiles, end)| (i, cost, tiles, end))
+            })
+            .min_by_key(|(_, cost, ..)| *cost);
+
+        let Some((i, cost, tiles, ends)) = best else {
+            break;
+        };
+        if cost > budget {
+            break;
+        }
+
+        budget -= cost;
+        pending.swap_remove(i);
+        for pos in tiles {
+            if built.insert(pos) {
+                moves.push(BotMove::Build(pos));
+            }
+            reaches.insert(pos, ends.clone());
+        }
+    }
+
+    moves
+}
+
+// Cheapest river that connects a single start, keeping every river that is already built
+pub fn connect(board: &Board, start: &TilePos) -> Option<(u32, Vec<TilePos>)> {
+    let (_, affinity, _) = board.starts.iter().find(|(pos, ..)| pos == start)?;
+
+    let mut reaches: HashMap<TilePos, Vec<TilePos>> = board
+        .ends
+        .iter()
+        .map(|(pos, _)| (*pos, vec![*pos]))
+        .collect();
+    let mut built = HashSet::new();
+    for (pos, path) in board.paths.iter() {
+        if board.starts.iter().any(|(p, ..)| p == pos) || reaches.contains_key(pos) {
+            continue;
+        }
+        if !path.ends.is_empty() {
+            reaches.insert(*pos, path.ends.clone());
+        }
+        built.insert(*pos);
+    }
+
+    route(board, start, affinity.as_ref(), &reaches, &built).map(|(cost, tiles, _)| {
+        let tiles = tiles
+            .into_iter()
+            .filter(|pos| !built.contains(pos))
+            .collect();
+        (cost, tiles)
+    })
+}
+
+// Cheapest river from a start to a tile that leads to an end of its color
+// Returns the cost, the tiles to build and the ends that the river would reach
+fn route(
+    board: &Board,
+    from: &TilePos,
+    affinity: Option<&Affinity>,
+    reaches: &HashMap<TilePos, Vec<TilePos>>,
+    built: &HashSet<TilePos>,
+) -> Option<(u32, Vec<TilePos>, Vec<TilePos>)> {
+    let matching = |pos: &TilePos| {
+        reaches.get(pos).and_then(|ends| {
+            let ends = ends
+                .iter()
+                .filter(|end| {
+                    board
+                        .ends
+                        .iter()
+                        .find(|(p, _)| p == *end)
+                        .map_or(false, |(_, other)| {
+                            Affinity::matches(affinity, other.as_ref())
+                        })
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            (!ends.is_empty()).then_some(ends)
+        })
+    };
+
+    let mut costs = HashMap::from([(*from, 0)]);
+    let mut previous = HashMap::new();
+    let mut open = BinaryHeap::from([Reverse((0, from.x, from.y))]);
+
+    // Djikstra's algorithm until a tile connected to a matching end is found
+    while let Some(Reverse((cost, x, y))) = open.pop() {
+        let pos = TilePos { x, y };
+        if cost > *costs.get(&pos).unwrap_or(&u32::MAX) {
+            continue;
+        }
+
+        if pos != *from {
+            if let Some(ends) = matching(&pos) {
+                let mut tiles = Vec::new();
+                let mut current = previous.get(&pos).copied();
+                while let Some(tile) = current.filter(|tile| tile != from) {
+                    tiles.push(tile);
+                    current = previous.get(&tile).copied();
+                }
+                return Some((cost, tiles, ends));
+            }
+        }
+
+        for neighbour in get_neighbours(&pos, &MAP_SIZE, &board.map_type) {
+            if !tile_in_level(&neighbour, &board.level_size) {
+                continue;
+            }
+
+            // Rivers can't go through other starts or ends of other colors
+            let is_end = board.ends.iter().any(|(p, _)| *p == neighbour);
+            if board.starts.iter().any(|(p, ..)| *p == neighbour)
+                || (is_end && matching(&neighbour).is_none())
+            {
+                continue;
+            }
+
+            // Going into a tile costs what it takes to build there, nothing if it is already a river
+            let step = if reaches.contains_key(&neighbour) || built.contains(&neighbour) {
+                match board.paths.get(&neighbour) {
+                    Some(path) if path.current && !reaches.contains_key(&neighbour) => continue,
+                    _ => 0,
+                }
+            } else {
+                let Some(step) = Terrain::cost(board.terrain.get(&neighbour)) else {
+                    continue;
+                };
+                step
+            };
+
+            let next = cost + step;
+            if next < *costs.get(&neighbour).unwrap_or(&u32::MAX) {
+                costs.insert(neighbour, next);
+                previous.insert(neighbour, pos);
+                open.push(Reverse((next, neighbour.x, neighbour.y)));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::play_to_real_size;
+
+    const LEVEL: TilemapSize = TilemapSize { x: 5, y: 3 };
+
+    // Position relative to the bottom left corner of the level
+    fn at(x: u32, y: u32) -> TilePos {
+        let (offset, _) = play_to_real_size(&LevelSize(LEVEL));
+        TilePos {
+            x: offset.x + x,
+            y: offset.y + y,
+        }
+    }
+
+    // A start on the left and an end on the right of the middle row
+    fn board(available: u32, terrain: &[((u32, u32), Terrain)]) -> Board {
+        Board {
+            map_type: TilemapType::default(),
+            level_size: LevelSize(LEVEL),
+            available,
+            terrain: terrain
+                .iter()
+                .map(|((x, y), terrain)| (at(*x, *y), *terrain))
+                .collect(),
+            starts: vec![(at(0, 1), None, false)],
+            ends: vec![(at(4, 1), None)],
+            paths: HashMap::new(),
+        }
+    }
+
+    fn built(moves: &[BotMove]) -> Vec<TilePos> {
+        let mut tiles = moves
+            .iter()
+            .filter(|m| matches!(m, BotMove::Build(_)))
+            .map(|m| m.pos())
+            .collect::<Vec<_>>();
+        tiles.sort_by_key(|pos| (pos.x, pos.y));
+        tiles
+    }
+
+    #[test]
+    fn crosses_marsh_when_cheaper() {
+        let board = board(10, &[((2, 1), Terrain::Marsh)]);
+        assert_eq!(built(&solve(&board)), vec![at(1, 1), at(2, 1), at(3, 1)]);
+    }
+
+    #[test]
+    fn waits_for_enough_tiles() {
+        // Through the marsh costs 4 and around it 5
+        let board = board(3, &[((2, 1), Terrain::Marsh)]);
+        assert!(solve(&board).is_empty());
+    }
+
+    #[test]
+    fn blocked_route() {
+        let rocks = [
+            ((2, 0), Terrain::Rock),
+            ((2, 1), Terrain::Rock),
+            ((2, 2), Terrain::Rock),
+        ];
+        assert!(solve(&board(10, &rocks)).is_empty());
+    }
+
+    #[test]
+    fn affinity_mismatch() {
+        let mut board = board(10, &[]);
+        board.starts[0].1 = Some(Affinity(0));
+        board.ends[0].1 = Some(Affinity(1));
+        assert!(solve(&board).is_empty());
+
+        // Another end of the right color is reached without going through the wrong one
+        board.ends.push((at(4, 2), Some(Affinity(0))));
+        let tiles = built(&solve(&board));
+        assert_eq!(tiles.len(), 4);
+        assert!(!tiles.contains(&at(4, 1)));
+        assert!(tiles.contains(&at(3, 2)));
+    }
+}
@@ -1,14 +1,18 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::input::Bind;
+use crate::{input::Bind, modifiers::Modifier};
 
 pub use bevy_persistent::prelude::*;
 
 pub const FONT_MULTIPLIERS: [f32; 3] = [2.0, 1.0, 0.8];
 pub const FONT_SIZES: [f32; 5] = [16.0, 20.0, 24.0, 28.0, 32.0];
+// Points taken from the score for each hint
+pub const HINT_PENALTIES: [u32; 3] = [0, 10, 50];
+// Runs kept in the score history
+pub const HISTORY_LEN: usize = 10;
 
 // ······
 // Plugin
@@ -64,13 +68,30 @@ impl Default for ColorPalette {
 pub struct GameOptions {
     pub font_size: FontSize,
     pub color: ColorPalette,
+    #[serde(default)]
+    pub color_match: bool,
+    #[serde(default)]
+    pub hex_grid: bool,
+    // Name of the tilemap skin, empty for the default one
+    #[serde(default)]
+    pub tileset: String,
+    #[serde(default)]
+    pub hint_penalty: u32,
+    // Modifiers chosen for the next run
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
 }
 
 // Keybinds
 
 #[derive(Resource, Serialize, Deserialize, Reflect)]
+#[serde(default)]
 pub struct Keybinds {
     pub interact: Vec<Bind>,
+    pub rotate_current: Vec<Bind>,
+    pub toggle_bridge: Vec<Bind>,
+    pub place_ferry: Vec<Bind>,
+    pub hint: Vec<Bind>,
     pub pause: Vec<Bind>,
 }
 
@@ -91,6 +112,24 @@ impl Default for Keybinds {
                 Bind::Mouse(MouseButton::Left),
                 Bind::Gamepad(GamepadButtonType::East),
             ],
+            rotate_current: vec![
+                Bind::Key(KeyCode::R),
+                Bind::Mouse(MouseButton::Right),
+                Bind::Gamepad(GamepadButtonType::North),
+            ],
+            toggle_bridge: vec![
+                Bind::Key(KeyCode::B),
+                Bind::Mouse(MouseButton::Middle),
+                Bind::Gamepad(GamepadButtonType::West),
+            ],
+            place_ferry: vec![
+                Bind::Key(KeyCode::F),
+                Bind::Gamepad(GamepadButtonType::RightTrigger),
+            ],
+            hint: vec![
+                Bind::Key(KeyCode::H),
+                Bind::Gamepad(GamepadButtonType::South),
+            ],
             pause: vec![
                 Bind::Key(KeyCode::Escape),
                 Bind::Gamepad(GamepadButtonType::Start),
@@ -103,9 +142,44 @@ impl Default for Keybinds {
 pub struct GameScore {
     #[serde(skip_serializing, skip_deserializing)]
     pub score: u32,
+    // Points delivered in this run without the modifier bonus, used to pace the run
+    #[serde(skip_serializing, skip_deserializing)]
+    pub delivered: u32,
     #[serde(skip_serializing, skip_deserializing)]
     pub last_score: u32,
     pub best_score: u32,
+    // Best results for the other modes
+    #[serde(default)]
+    pub best_zen: u32,
+    #[serde(default)]
+    pub best_time_attack: u32,
+    // Most tiles left over on each puzzle (by name)
+    #[serde(default)]
+    pub best_puzzles: HashMap<String, u32>,
+    // Day of the last daily challenge played and its score
+    #[serde(default)]
+    pub last_daily: u32,
+    #[serde(default)]
+    pub daily_score: u32,
+    #[serde(default)]
+    pub best_daily: u32,
+    // Last runs played, the newest at the end
+    #[serde(default)]
+    pub history: Vec<RunRecord>,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone)]
+pub struct RunRecord {
+    pub mode: String,
+    pub score: u32,
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+}
+
+// Best stars obtained on each campaign level (by name)
+#[derive(Resource, Serialize, Deserialize, Reflect, Default)]
+pub struct CampaignProgress {
+    pub stars: HashMap<String, u32>,
 }
 
 // ·······
@@ -153,4 +227,16 @@ fn init_persistence(mut cmd: Commands) {
             .build()
             .expect("Failed to initialize game score"),
     );
+
+    cmd.insert_resource(
+        Persistent::<CampaignProgress>::builder()
+            .name("campaign")
+            .format(StorageFormat::Toml)
+            .path(config_dir.join("campaign.toml"))
+            .default(CampaignProgress::default())
+            .revertible(true)
+            .revert_to_default_on_deserialization_errors(true)
+            .build()
+            .expect("Failed to initialize campaign progress"),
+    );
 }
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use chrono::{Datelike, Local};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::GameState;
+
+// ······
+// Plugin
+// ······
+
+// A run that starts the same for everyone on the same day, playable once
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_countdown.run_if(in_state(GameState::Menu)));
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Rules of today's challenge, all of them derived from the local date
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Daily {
+    pub day: u32,
+    pub seed: u64,
+    // Percentage of the usual score needed for each new start and end
+    pub spawn_pace: u32,
+    // Tiles available at the start of the run
+    pub tiles: u32,
+    // Multiplier for the chances of rocks and marshes
+    pub terrain: f64,
+    pub color_match: bool,
+}
+
+impl Daily {
+    pub fn today() -> Self {
+        let day = today();
+        // Spread consecutive days so their seeds don't look alike
+        let seed = (day as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        Self {
+            day,
+            seed,
+            spawn_pace: rng.gen_range(70..=130),
+            tiles: rng.gen_range(7..=12),
+            terrain: rng.gen_range(0.5..2.),
+            color_match: rng.gen_bool(0.4),
+        }
+    }
+}
+
+// ··········
+// Components
+// ··········
+
+#[derive(Component)]
+pub struct DailyText;
+
+// ·······
+// Systems
+// ·······
+
+fn update_countdown(mut text: Query<&mut Text, With<DailyText>>) {
+    for mut text in text.iter_mut() {
+        text.sections[0].value = countdown_text();
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Days since the common era in the local time zone
+pub fn today() -> u32 {
+    Local::now().date_naive().num_days_from_ce() as u32
+}
+
+pub fn countdown_text() -> String {
+    let now = Local::now().naive_local();
+    let secs = now
+        .date()
+        .succ_opt()
+        .and_then(|next| next.and_hms_opt(0, 0, 0))
+        .map_or(0, |next| (next - now).num_seconds().max(0));
+    format!(
+        "Next daily in {}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
@@ -13,7 +13,7 @@ pub struct DebugPlugin;
 // Only debug implementation
 #[cfg(debug_assertions)]
 mod only_in_debug {
-    use crate::{load::StartAssets, ui::*, GameState};
+    use crate::{bot::Bot, load::StartAssets, ui::*, GameState};
     use bevy::{
         diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
         ecs::schedule::ScheduleLabel,
@@ -112,10 +112,18 @@ mod only_in_debug {
         }
     }
 
-    fn handle_keys(mut state: ResMut<DebugState>, keyboard: Res<Input<KeyCode>>) {
+    fn handle_keys(
+        mut state: ResMut<DebugState>,
+        mut bot: ResMut<Bot>,
+        keyboard: Res<Input<KeyCode>>,
+    ) {
         if keyboard.just_pressed(KeyCode::I) {
             state.inspector = !state.inspector;
         }
+        // Let the bot play
+        if keyboard.just_pressed(KeyCode::P) {
+            bot.active = !bot.active;
+        }
     }
 
     // ·····
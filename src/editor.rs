@@ -0,0 +1,592 @@
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+
+use std::path::PathBuf;
+
+use bevy::{prelude::*, window::ReceivedCharacter};
+use bevy_ecs_tilemap::prelude::*;
+use bevy_persistent::Persistent;
+
+use crate::{
+    config::{GameOptions, Keybinds},
+    input::Bind,
+    level::{
+        insert_level_tiles, save_level, CurrentLevel, Level, LevelDecoration, LevelGoal, LevelSpawn,
+    },
+    spirits::INITIAL_SPAWN_TIME,
+    tilemap::{
+        insert_start_end, play_to_real_size, tile_in_level, Affinity, EndTile, ForegroundTile,
+        LevelSize, PathTile, SelectedPos, StartTile, Terrain, TilemapLayer, AFFINITY_COLORS,
+        MAP_SIZE,
+    },
+    ui::*,
+    GameState,
+};
+
+// Foreground textures that can be used as decorations
+const DECORATIONS: [u32; 3] = [10, 9, 8];
+const MIN_SIZE: TilemapSize = TilemapSize { x: 4, y: 3 };
+const MAX_SIZE: TilemapSize = TilemapSize { x: 24, y: 19 };
+const MAX_NAME_LEN: usize = 24;
+// Score needed for a start or end to appear
+const SPAWN_SCORES: [u32; 5] = [0, 10, 25, 50, 100];
+// Seconds between the spirits of a start, none uses the default
+const SPAWN_TIMES: [Option<f32>; 4] = [None, Some(0.8), Some(2.), Some(3.)];
+
+// ······
+// Plugin
+// ······
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Editor), init_editor)
+            .add_systems(
+                Update,
+                (
+                    handle_buttons,
+                    type_name.run_if(resource_exists::<EditorNaming>()),
+                    paint_tile.run_if(not(resource_exists::<EditorNaming>())),
+                    sync_level.run_if(
+                        resource_exists::<LevelSize>().and_then(resource_changed::<EditorLevel>()),
+                    ),
+                    update_editor_text,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Editor)),
+            )
+            .add_systems(OnExit(GameState::Editor), exit_editor);
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Level being edited and the file it was loaded from
+#[derive(Resource, Default)]
+pub struct EditorLevel {
+    pub level: Level,
+    pub path: Option<PathBuf>,
+}
+
+// Marks that the current run is a test of the level in the editor
+#[derive(Resource)]
+pub struct EditorTest;
+
+// The name of the level is being typed
+#[derive(Resource)]
+struct EditorNaming;
+
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+enum EditorTool {
+    Terrain(Terrain),
+    Start,
+    End,
+    Decoration,
+    // Cycle when a start or end appears
+    Delay,
+    // Cycle how often a start sends spirits
+    Pace,
+    Erase,
+}
+
+impl Default for EditorTool {
+    fn default() -> Self {
+        Self::Terrain(Terrain::Rock)
+    }
+}
+
+// ··········
+// Components
+// ··········
+
+#[derive(Component)]
+struct EditorUi;
+
+#[derive(Component)]
+struct EditorText;
+
+#[derive(Component)]
+enum EditorButton {
+    Tool(EditorTool),
+    Grow,
+    Shrink,
+    MoreTiles,
+    FewerTiles,
+    MoreGoal,
+    FewerGoal,
+    Rename,
+    Test,
+    Save,
+}
+
+// ·······
+// Systems
+// ·······
+
+fn init_editor(
+    mut cmd: Commands,
+    style: Res<UIStyle>,
+    node: Query<Entity, With<UiNode>>,
+    editor: Option<ResMut<EditorLevel>>,
+) {
+    // Keep editing the previous level (for example after testing it)
+    match editor {
+        Some(mut editor) => editor.set_changed(),
+        None => cmd.insert_resource(EditorLevel::default()),
+    }
+    cmd.insert_resource(EditorTool::default());
+    cmd.remove_resource::<EditorTest>();
+    cmd.remove_resource::<EditorNaming>();
+    cmd.remove_resource::<CurrentLevel>();
+
+    // Toolbar
+    if let Ok(node) = node.get_single() {
+        if let Some(mut node) = cmd.get_entity(node) {
+            node.with_children(|parent| {
+                parent
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                position_type: PositionType::Absolute,
+                                left: Val::Px(5.0),
+                                top: Val::Px(5.0),
+                                flex_direction: FlexDirection::Column,
+                                row_gap: Val::Px(4.),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        EditorUi,
+                        UI_LAYER,
+                    ))
+                    .with_children(|bar| {
+                        UIText::new(&style, "", Some(EditorText)).add(bar);
+
+                        let buttons = [
+                            (
+                                "Rock",
+                                EditorButton::Tool(EditorTool::Terrain(Terrain::Rock)),
+                            ),
+                            (
+                                "Marsh",
+                                EditorButton::Tool(EditorTool::Terrain(Terrain::Marsh)),
+                            ),
+                            (
+                                "River",
+                                EditorButton::Tool(EditorTool::Terrain(Terrain::River)),
+                            ),
+                            (
+                                "Bridge",
+                                EditorButton::Tool(EditorTool::Terrain(Terrain::Bridge)),
+                            ),
+                            ("Start", EditorButton::Tool(EditorTool::Start)),
+                            ("End", EditorButton::Tool(EditorTool::End)),
+                            ("Decoration", EditorButton::Tool(EditorTool::Decoration)),
+                            ("Delay", EditorButton::Tool(EditorTool::Delay)),
+                            ("Pace", EditorButton::Tool(EditorTool::Pace)),
+                            ("Erase", EditorButton::Tool(EditorTool::Erase)),
+                            ("Size +", EditorButton::Grow),
+                            ("Size -", EditorButton::Shrink),
+                            ("Tiles +", EditorButton::MoreTiles),
+                            ("Tiles -", EditorButton::FewerTiles),
+                            ("Goal +", EditorButton::MoreGoal),
+                            ("Goal -", EditorButton::FewerGoal),
+                            ("Rename", EditorButton::Rename),
+                            ("Test", EditorButton::Test),
+                            ("Save", EditorButton::Save),
+                        ];
+                        for (text, button) in buttons {
+                            UIButton::new(&style, text, Some(button))
+                                .with_width(Val::Px(120.))
+                                .with_font_scale(0.8)
+                                .add(bar);
+                        }
+                    });
+            });
+        }
+    }
+}
+
+fn exit_editor(mut cmd: Commands, ui: Query<Entity, With<EditorUi>>) {
+    for entity in ui.iter() {
+        cmd.entity(entity).despawn_recursive();
+    }
+}
+
+fn handle_buttons(
+    mut cmd: Commands,
+    mut state: ResMut<NextState<GameState>>,
+    mut editor: ResMut<EditorLevel>,
+    mut tool: ResMut<EditorTool>,
+    mut levels: ResMut<Assets<Level>>,
+    mut text: Query<&mut Text>,
+    mut buttons: Query<
+        (&Interaction, &EditorButton, &Children, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    opts: Res<Persistent<GameOptions>>,
+    naming: Option<Res<EditorNaming>>,
+) {
+    for (inter, button, child, mut bg) in &mut buttons {
+        let child = child.iter().next();
+        if let Some(mut text) = child.and_then(|child| text.get_mut(*child).ok()) {
+            match inter {
+                Interaction::Pressed => {
+                    bg.0 = opts.color.dark;
+                    text.sections[0].style.color = opts.color.light;
+
+                    // Any button stops typing the name
+                    if naming.is_some() {
+                        cmd.remove_resource::<EditorNaming>();
+                    }
+
+                    let level = &mut editor.level;
+                    match button {
+                        EditorButton::Tool(new_tool) => {
+                            *tool = *new_tool;
+                        }
+                        EditorButton::Grow => {
+                            level.size.0 = (level.size.0 + 2).min(MAX_SIZE.x);
+                            level.size.1 = (level.size.1 + 2).min(MAX_SIZE.y);
+                        }
+                        EditorButton::Shrink => {
+                            level.size.0 = level.size.0.saturating_sub(2).max(MIN_SIZE.x);
+                            level.size.1 = level.size.1.saturating_sub(2).max(MIN_SIZE.y);
+
+                            // Remove everything that is now outside of the level
+                            let size = level.size;
+                            let inside = |pos: &(u32, u32)| pos.0 < size.0 && pos.1 < size.1;
+                            level.terrain.retain(|(pos, _)| inside(pos));
+                            level.starts.retain(|start| inside(&start.pos));
+                            level.ends.retain(|end| inside(&end.pos));
+                            level.decorations.retain(|deco| inside(&deco.pos));
+                        }
+                        EditorButton::MoreTiles => {
+                            level.tiles += 1;
+                        }
+                        EditorButton::FewerTiles => {
+                            level.tiles = level.tiles.saturating_sub(1);
+                        }
+                        EditorButton::MoreGoal | EditorButton::FewerGoal => {
+                            let more = matches!(button, EditorButton::MoreGoal);
+                            level.goal = match level.goal {
+                                LevelGoal::Deliver(count) => LevelGoal::Deliver(if more {
+                                    count + 5
+                                } else {
+                                    count.saturating_sub(5).max(5)
+                                }),
+                                LevelGoal::Survive(time) => LevelGoal::Survive(if more {
+                                    time + 10.
+                                } else {
+                                    (time - 10.).max(10.)
+                                }),
+                            };
+                        }
+                        EditorButton::Rename => {
+                            if naming.is_none() {
+                                cmd.insert_resource(EditorNaming);
+                            }
+                        }
+                        EditorButton::Test => {
+                            cmd.insert_resource(CurrentLevel(levels.add(level.clone())));
+                            cmd.insert_resource(EditorTest);
+                            state.set(GameState::Play);
+                        }
+                        EditorButton::Save => {
+                            match save_level(&editor.level, editor.path.as_deref()) {
+                                Ok(path) => {
+                                    info!("Level saved to {:?}", path);
+                                    editor.path = Some(path);
+                                }
+                                Err(e) => error!("Failed to save level: {}", e),
+                            }
+                        }
+                    }
+                }
+                Interaction::Hovered => {
+                    bg.0 = opts.color.mid;
+                    text.sections[0].style.color = opts.color.dark;
+                }
+                Interaction::None => {
+                    bg.0 = opts.color.light;
+                    text.sections[0].style.color = opts.color.dark;
+                }
+            }
+        }
+    }
+}
+
+// Type the name of the level, enter finishes it
+fn type_name(
+    mut cmd: Commands,
+    mut chars: EventReader<ReceivedCharacter>,
+    keyboard: Res<Input<KeyCode>>,
+    mut editor: ResMut<EditorLevel>,
+) {
+    // Only mark the level as changed if the name was edited
+    let name = &mut editor.bypass_change_detection().level.name;
+    let before = name.clone();
+    for event in chars.read() {
+        if !event.char.is_control() && name.chars().count() < MAX_NAME_LEN {
+            name.push(event.char);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Back) {
+        name.pop();
+    }
+
+    let done = keyboard.just_pressed(KeyCode::Return);
+    if done {
+        cmd.remove_resource::<EditorNaming>();
+    }
+    if done || *name != before {
+        editor.set_changed();
+    }
+}
+
+fn paint_tile(
+    input: Res<Input<Bind>>,
+    keybinds: Res<Persistent<Keybinds>>,
+    sel_pos: Res<SelectedPos>,
+    level_size: Res<LevelSize>,
+    tool: Res<EditorTool>,
+    mut editor: ResMut<EditorLevel>,
+    buttons: Query<&Interaction, With<EditorButton>>,
+) {
+    // Don't paint below the toolbar
+    if buttons.iter().any(|inter| *inter != Interaction::None) {
+        return;
+    }
+
+    // Terrain and erasing can be dragged, the rest are placed one click at a time
+    let drag = matches!(*tool, EditorTool::Terrain(_) | EditorTool::Erase);
+    let pressed = keybinds.interact.iter().any(|bind| {
+        if drag {
+            input.pressed(*bind)
+        } else {
+            input.just_pressed(*bind)
+        }
+    });
+    if !pressed {
+        return;
+    }
+
+    // The selection can be left outside of the level after shrinking it
+    let Some(pos) = sel_pos.0.filter(|pos| tile_in_level(pos, &level_size)) else {
+        return;
+    };
+    let (offset, _) = play_to_real_size(&level_size);
+    let pos = (pos.x - offset.x, pos.y - offset.y);
+
+    // Only mark the level as changed if something was actually painted
+    let level = &mut editor.bypass_change_detection().level;
+    let changed = match *tool {
+        EditorTool::Terrain(terrain) => {
+            if level.terrain.contains(&(pos, terrain)) {
+                false
+            } else {
+                clear_pos(level, pos);
+                level.terrain.push((pos, terrain));
+                true
+            }
+        }
+        EditorTool::Start | EditorTool::End => {
+            let spawns = if *tool == EditorTool::Start {
+                &mut level.starts
+            } else {
+                &mut level.ends
+            };
+
+            // Clicking an existing one cycles through the colors
+            if let Some(spawn) = spawns.iter_mut().find(|spawn| spawn.pos == pos) {
+                spawn.affinity = match spawn.affinity {
+                    None => Some(0),
+                    Some(i) if i + 1 < AFFINITY_COLORS.len() => Some(i + 1),
+                    _ => None,
+                };
+            } else {
+                clear_pos(level, pos);
+                let spawn = LevelSpawn {
+                    pos,
+                    score: 0,
+                    spawn_time: None,
+                    affinity: None,
+                };
+                if *tool == EditorTool::Start {
+                    level.starts.push(spawn);
+                } else {
+                    level.ends.push(spawn);
+                }
+            }
+            true
+        }
+        EditorTool::Decoration => {
+            if let Some(deco) = level.decorations.iter_mut().find(|deco| deco.pos == pos) {
+                let i = DECORATIONS.iter().position(|t| *t == deco.texture);
+                deco.texture = DECORATIONS[i.map_or(0, |i| (i + 1) % DECORATIONS.len())];
+            } else {
+                clear_pos(level, pos);
+                level.decorations.push(LevelDecoration {
+                    pos,
+                    texture: DECORATIONS[0],
+                });
+            }
+            true
+        }
+        EditorTool::Delay => {
+            let spawn = level
+                .starts
+                .iter_mut()
+                .chain(level.ends.iter_mut())
+                .find(|spawn| spawn.pos == pos);
+            if let Some(spawn) = spawn {
+                let i = SPAWN_SCORES.iter().position(|score| *score == spawn.score);
+                spawn.score = SPAWN_SCORES[i.map_or(0, |i| (i + 1) % SPAWN_SCORES.len())];
+                true
+            } else {
+                false
+            }
+        }
+        EditorTool::Pace => {
+            if let Some(start) = level.starts.iter_mut().find(|start| start.pos == pos) {
+                let i = SPAWN_TIMES
+                    .iter()
+                    .position(|time| *time == start.spawn_time);
+                start.spawn_time = SPAWN_TIMES[i.map_or(0, |i| (i + 1) % SPAWN_TIMES.len())];
+                true
+            } else {
+                false
+            }
+        }
+        EditorTool::Erase => clear_pos(level, pos),
+    };
+
+    if changed {
+        editor.set_changed();
+    }
+}
+
+// Rebuild the tilemap from the level data
+fn sync_level(
+    mut cmd: Commands,
+    editor: Res<EditorLevel>,
+    mut level_size: ResMut<LevelSize>,
+    tilemap: Query<(&TilemapLayer, &TileStorage)>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    let level = &editor.level;
+    level_size.0 = TilemapSize {
+        x: level.size.0.min(MAP_SIZE.x),
+        y: level.size.1.min(MAP_SIZE.y),
+    };
+    let (offset, _) = play_to_real_size(&level_size);
+
+    for (layer, storage) in tilemap.iter() {
+        // Clear all tiles
+        for entity in storage.iter().flatten() {
+            cmd.entity(*entity).remove::<(
+                StartTile,
+                EndTile,
+                PathTile,
+                ForegroundTile,
+                Affinity,
+                Terrain,
+            )>();
+            if let Ok(mut visible) = visible.get_mut(*entity) {
+                visible.0 = matches!(layer, TilemapLayer::Background);
+            }
+        }
+
+        // Add the level tiles
+        insert_level_tiles(&mut cmd, level, &level_size, layer, storage, &mut visible);
+        for (spawns, is_start) in [(&level.starts, true), (&level.ends, false)] {
+            for spawn in spawns.iter() {
+                insert_start_end(
+                    &mut cmd,
+                    &TilePos {
+                        x: offset.x + spawn.pos.0,
+                        y: offset.y + spawn.pos.1,
+                    },
+                    is_start.then(StartTile::default),
+                    spawn.affinity.map(Affinity),
+                    layer,
+                    storage,
+                    &mut visible,
+                );
+            }
+        }
+    }
+}
+
+fn update_editor_text(
+    editor: Res<EditorLevel>,
+    tool: Res<EditorTool>,
+    naming: Option<Res<EditorNaming>>,
+    sel_pos: Res<SelectedPos>,
+    level_size: Option<Res<LevelSize>>,
+    mut text: Query<&mut Text, With<EditorText>>,
+) {
+    let level = &editor.level;
+    let name = if naming.is_some() {
+        format!("{}_", level.name)
+    } else {
+        level.name.clone()
+    };
+    let goal = match level.goal {
+        LevelGoal::Deliver(count) => format!("deliver {}", count),
+        LevelGoal::Survive(time) => format!("survive {}s", time),
+    };
+
+    // Schedule of the start or end under the cursor
+    let pos = sel_pos
+        .0
+        .zip(level_size)
+        .filter(|(pos, level_size)| tile_in_level(pos, level_size))
+        .map(|(pos, level_size)| {
+            let (offset, _) = play_to_real_size(&level_size);
+            (pos.x - offset.x, pos.y - offset.y)
+        });
+    let start = level.starts.iter().find(|start| Some(start.pos) == pos);
+    let end = level.ends.iter().find(|end| Some(end.pos) == pos);
+    let schedule = match (start, end) {
+        (Some(start), _) => format!(
+            "\nStart at {}, every {}s",
+            start.score,
+            start.spawn_time.unwrap_or(INITIAL_SPAWN_TIME)
+        ),
+        (_, Some(end)) => format!("\nEnd at {}", end.score),
+        _ => String::new(),
+    };
+
+    let value = format!(
+        "{}\n{:?}\nSize {}x{}\nTiles {}\nGoal: {}{}",
+        name, *tool, level.size.0, level.size.1, level.tiles, goal, schedule
+    );
+    for mut text in text.iter_mut() {
+        // Only change the text when needed, the cursor moves every frame
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Remove anything placed at this position, returns if there was something
+fn clear_pos(level: &mut Level, pos: (u32, u32)) -> bool {
+    let before = level.terrain.len() + level.starts.len() + level.ends.len();
+    let decorations = level.decorations.len();
+
+    level.terrain.retain(|(p, _)| *p != pos);
+    level.starts.retain(|start| start.pos != pos);
+    level.ends.retain(|end| end.pos != pos);
+    level.decorations.retain(|deco| deco.pos != pos);
+
+    before + decorations
+        != level.terrain.len() + level.starts.len() + level.ends.len() + level.decorations.len()
+}
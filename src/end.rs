@@ -1,5 +1,10 @@
+#![allow(clippy::too_many_arguments)]
+
 use crate::{
     config::{GameOptions, GameScore},
+    editor::EditorTest,
+    game::{GameMode, RunTimer},
+    level::{clear_level, CurrentLevel, Level, LevelResult, RestartRun},
     menu::MenuState,
     ui::*,
     GameState,
@@ -15,7 +20,8 @@ pub struct EndScreenPlugin;
 
 impl Plugin for EndScreenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::End), init_end_screen)
+        // The level is needed to tell if it was lost, so read it before it is cleared
+        app.add_systems(OnEnter(GameState::End), init_end_screen.before(clear_level))
             .add_systems(Update, handle_buttons.run_if(in_state(GameState::End)))
             .add_systems(OnExit(GameState::End), exit_end_screen);
     }
@@ -30,27 +36,109 @@ fn init_end_screen(
     style: Res<UIStyle>,
     mut node: Query<Entity, With<UiNode>>,
     score: Res<Persistent<GameScore>>,
+    result: Option<Res<LevelResult>>,
+    restart: Option<Res<RestartRun>>,
+    test: Option<Res<EditorTest>>,
+    mode: Res<GameMode>,
+    timer: Option<Res<RunTimer>>,
+    level: Option<Res<CurrentLevel>>,
+    levels: Res<Assets<Level>>,
 ) {
+    // Going straight into another run
+    if restart.is_some() {
+        return;
+    }
+
     if let Ok(node) = node.get_single_mut() {
         if let Some(mut node) = cmd.get_entity(node) {
             node.with_children(|parent| {
-                UIText::simple(&style, "Your journey has ended").add(parent);
+                if let Some(result) = result {
+                    if *mode == GameMode::Puzzle {
+                        UIText::simple(&style, &format!("{} solved", result.name))
+                            .with_title()
+                            .add(parent);
+                        UIText::simple(
+                            &style,
+                            &format!("You had {} tiles left over", result.tiles_left),
+                        )
+                        .add(parent);
+                        let best = score.best_puzzles.get(&result.name).copied();
+                        let best = best.unwrap_or(result.tiles_left);
+                        UIText::simple(&style, &format!("Best {}", best)).add(parent);
+                        UIButton::<UiNone>::new(&style, "Continue", None).add(parent);
+                        return;
+                    }
+
+                    UIText::simple(&style, &format!("{} completed", result.name))
+                        .with_title()
+                        .add(parent);
+                    UIText::simple(&style, &format!("You earned {} of 3 stars", result.stars))
+                        .add(parent);
+                    let text = if test.is_some() {
+                        "Back to editor"
+                    } else {
+                        "Continue"
+                    };
+                    UIButton::<UiNone>::new(&style, text, None).add(parent);
+                    return;
+                }
+
+                // Levels and puzzles that end without a result were lost
+                if let Some(level) = level.and_then(|level| levels.get(&level.0)) {
+                    UIText::simple(&style, &format!("{} failed", level.name))
+                        .with_title()
+                        .add(parent);
+                    UIText::simple(&style, "A start waited for too long").add(parent);
+                    let text = if test.is_some() {
+                        "Back to editor"
+                    } else {
+                        "Continue"
+                    };
+                    UIButton::<UiNone>::new(&style, text, None).add(parent);
+                    return;
+                }
+
+                // Dirty hack to avoid dealing with system ordering
+                let helped = if score.score > 0 {
+                    score.score
+                } else {
+                    score.last_score
+                };
+                let time_up = timer.is_some_and(|timer| timer.0.finished());
+
+                let title = match *mode {
+                    GameMode::Zen => "The river rests",
+                    GameMode::TimeAttack if time_up => "Time is up",
+                    GameMode::Daily => "Come back tomorrow for a new challenge",
+                    _ => "Your journey has ended",
+                };
+                UIText::simple(&style, title).add(parent);
                 UIText::simple(
                     &style,
-                    &format!(
-                        "You helped {} entities find their way home",
-                        if score.score > 0 {
-                            // Dirty hack to avoid dealing with system ordering
-                            score.score
-                        } else {
-                            score.last_score
-                        }
-                    ),
+                    &format!("You helped {} entities find their way home", helped),
                 )
                 .add(parent);
-                UIText::simple(&style, "Thank you").add(parent);
 
-                UIButton::<UiNone>::new(&style, "Try again", None).add(parent);
+                let best = match *mode {
+                    GameMode::Zen => Some(score.best_zen),
+                    GameMode::TimeAttack => Some(score.best_time_attack),
+                    GameMode::Daily => Some(score.best_daily),
+                    _ => None,
+                };
+                if let Some(best) = best {
+                    UIText::simple(&style, &format!("Best {}", best.max(helped))).add(parent);
+                } else {
+                    UIText::simple(&style, "Thank you").add(parent);
+                }
+
+                let text = if test.is_some() {
+                    "Back to editor"
+                } else if *mode == GameMode::Daily {
+                    "Continue"
+                } else {
+                    "Try again"
+                };
+                UIButton::<UiNone>::new(&style, text, None).add(parent);
             });
         }
     }
@@ -62,6 +150,7 @@ fn handle_buttons(
     mut text: Query<&mut Text>,
     mut buttons: Query<(&Interaction, &Children, &mut BackgroundColor), Changed<Interaction>>,
     opts: Res<Persistent<GameOptions>>,
+    test: Option<Res<EditorTest>>,
 ) {
     for (inter, child, mut bg) in &mut buttons {
         let child = child.iter().next();
@@ -70,9 +159,13 @@ fn handle_buttons(
                 Interaction::Pressed => {
                     bg.0 = opts.color.dark;
                     text.sections[0].style.color = opts.color.light;
-                    // Go to the main menu
-                    menu_state.set(MenuState::Main);
-                    game_state.set(GameState::Menu);
+                    // Go back to the editor if testing a level, otherwise to the main menu
+                    if test.is_some() {
+                        game_state.set(GameState::Editor);
+                    } else {
+                        menu_state.set(MenuState::Main);
+                        game_state.set(GameState::Menu);
+                    }
                 }
                 Interaction::Hovered => {
                     bg.0 = opts.color.mid;
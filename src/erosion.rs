@@ -0,0 +1,159 @@
+#![allow(clippy::type_complexity)]
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+
+use crate::{
+    ferry::Ferry,
+    modifiers::{Modifier, RunModifiers},
+    spirits::Spirit,
+    tilemap::{EndTile, NeedsReset, PathTile, StartTile, Terrain, TilesAvailable},
+    GameState,
+};
+
+// Seconds without spirits before a river silts up
+const SILT_TIME: f32 = 45.;
+// Part of the silt time after which the river starts to look muddy
+const SILT_WARNING: f32 = 0.6;
+// Seconds of spirits going through a river for each level of depth
+const DEEPEN_TIME: f32 = 20.;
+const MAX_DEPTH: u32 = 2;
+
+// ······
+// Plugin
+// ······
+
+// Decaying rivers modifier, unused rivers disappear and busy ones get deeper
+pub struct ErosionPlugin;
+
+impl Plugin for ErosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SiltRefund>()
+            .add_systems(
+                OnEnter(GameState::Play),
+                init_refund.run_if(resource_exists::<NeedsReset>()),
+            )
+            .add_systems(
+                Update,
+                erode.run_if(
+                    in_state(GameState::Play)
+                        .and_then(resource_exists::<TilesAvailable>())
+                        .and_then(decay_active),
+                ),
+            );
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Halves of a tile from silted rivers that were not given back yet
+#[derive(Resource, Default)]
+struct SiltRefund(u32);
+
+// ··········
+// Components
+// ··········
+
+#[derive(Component, Default)]
+pub struct Erosion {
+    // Seconds since the last spirit went through
+    idle: f32,
+    // Seconds that spirits have spent on it since the last change of depth
+    used: f32,
+}
+
+impl Erosion {
+    pub fn silting(&self) -> bool {
+        self.idle > SILT_TIME * SILT_WARNING
+    }
+}
+
+// ·······
+// Systems
+// ·······
+
+fn init_refund(mut refund: ResMut<SiltRefund>) {
+    *refund = SiltRefund::default();
+}
+
+fn decay_active(modifiers: Res<RunModifiers>) -> bool {
+    modifiers.has(Modifier::Decay)
+}
+
+fn erode(
+    mut cmd: Commands,
+    time: Res<Time>,
+    mut available: ResMut<TilesAvailable>,
+    mut rivers: Query<
+        (
+            Entity,
+            &TilePos,
+            &mut PathTile,
+            Option<&mut Erosion>,
+            Option<&Terrain>,
+            &mut TileVisible,
+        ),
+        (Without<StartTile>, Without<EndTile>),
+    >,
+    spirits: Query<&Spirit>,
+    ferries: Query<&Ferry>,
+    mut refund: ResMut<SiltRefund>,
+) {
+    // Rivers that ferries are sailing through are also in use
+    let occupied = spirits
+        .iter()
+        .map(|spirit| spirit.tile())
+        .chain(
+            ferries
+                .iter()
+                .flat_map(|ferry| ferry.route().iter().copied()),
+        )
+        .collect::<HashSet<_>>();
+    let delta = time.delta_seconds();
+
+    for (entity, pos, mut path, erosion, terrain, mut visible) in rivers.iter_mut() {
+        // Rivers that were just built start clean
+        let Some(mut erosion) = erosion.filter(|_| !path.is_added()) else {
+            cmd.entity(entity).insert(Erosion::default());
+            continue;
+        };
+
+        if occupied.contains(pos) {
+            erosion.idle = 0.;
+            erosion.used += delta;
+            if path.depth < MAX_DEPTH && erosion.used > DEEPEN_TIME {
+                path.depth += 1;
+                erosion.used = 0.;
+            }
+            continue;
+        }
+
+        erosion.idle += delta;
+        if erosion.idle < SILT_TIME || path.count > 0 {
+            continue;
+        }
+        erosion.idle = 0.;
+        erosion.used = 0.;
+
+        // Deep rivers get shallower before silting up completely
+        if path.depth > 0 {
+            path.depth -= 1;
+            continue;
+        }
+
+        // Give back half of what it took to build it, erasing it by hand gives back all of it
+        let natural_bridge = terrain == Some(&Terrain::Bridge);
+        let cost = Terrain::cost(terrain).unwrap_or(0) + u32::from(path.bridge && !natural_bridge);
+        refund.0 += cost;
+        available.0 += refund.0 / 2;
+        refund.0 %= 2;
+        available.set_changed();
+
+        cmd.entity(entity).remove::<(PathTile, Erosion)>();
+        visible.0 = false;
+    }
+}
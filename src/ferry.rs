@@ -0,0 +1,497 @@
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_persistent::Persistent;
+
+use crate::{
+    aseprite::Aseprite,
+    config::Keybinds,
+    input::Bind,
+    load::{SpiritAssets, StartAssets},
+    shop::{Shop, ShopOpen},
+    spirits::{spawn_spirit_at, Spirit, SpiritKind},
+    tilemap::{
+        can_flow, get_neighbours, tile_to_pos, Affinity, EndTile, PathTile, SelectedTile,
+        StartTile, TilemapLayer,
+    },
+    GameState,
+};
+
+const FERRY_SPEED: f32 = 240.;
+const FERRY_SIZE: Vec2 = Vec2::new(90., 40.);
+const FERRY_COLOR: Color = Color::rgb(0.45, 0.3, 0.25);
+// Places on a ferry, heavy spirits take two
+const FERRY_CAPACITY: u32 = 4;
+// Each upgrade level adds places and speed to every ferry
+const CAPACITY_STEP: u32 = 2;
+const SPEED_STEP: f32 = 0.25;
+// Seconds a ferry waits for more spirits after the first one boards
+const DOCK_TIME: f32 = 3.;
+
+// ······
+// Plugin
+// ······
+
+// Charon's ferries, boats that carry spirits from a start to an end and come back
+pub struct FerryPlugin;
+
+impl Plugin for FerryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                place_ferry.run_if(not(resource_exists::<ShopOpen>())),
+                (board_ferry, depart_ferry, sail_ferry, update_labels).chain(),
+            )
+                .run_if(in_state(GameState::Play)),
+        )
+        .add_systems(OnEnter(GameState::End), reset_ferries);
+    }
+}
+
+// ··········
+// Components
+// ··········
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FerryState {
+    Docked,
+    Sailing,
+    Returning,
+}
+
+#[derive(Component)]
+pub struct Ferry {
+    // Start where it picks up spirits
+    dock: TilePos,
+    state: FerryState,
+    // Tiles it is going through, the first one is where it left from
+    route: Vec<TilePos>,
+    step: usize,
+    passengers: Vec<(SpiritKind, Option<Affinity>)>,
+    wait: Timer,
+}
+
+impl Ferry {
+    fn new(dock: TilePos) -> Self {
+        Self {
+            dock,
+            state: FerryState::Docked,
+            route: Vec::new(),
+            step: 0,
+            passengers: Vec::new(),
+            wait: Timer::from_seconds(DOCK_TIME, TimerMode::Once),
+        }
+    }
+
+    // Tiles of the trip it is on, empty while docked
+    pub fn route(&self) -> &[TilePos] {
+        &self.route
+    }
+
+    fn load(&self) -> u32 {
+        self.passengers.iter().map(|(kind, _)| kind.weight()).sum()
+    }
+
+    // A spirit can board if it is docked and there is room for it
+    // All the passengers must be able to go to the same end
+    fn has_room(&self, kind: SpiritKind, affinity: Option<&Affinity>, level: u32) -> bool {
+        if self.state != FerryState::Docked || self.load() + kind.weight() > capacity(level) {
+            return false;
+        }
+        self.passengers.first().map_or(true, |(_, first)| {
+            Affinity::matches(first.as_ref(), affinity)
+        })
+    }
+
+    // Go back to the dock through the tiles it already went through
+    fn turn_back(&mut self) {
+        self.route.truncate(self.step);
+        self.route.reverse();
+        self.step = 0;
+        self.state = FerryState::Returning;
+    }
+
+    fn moor(&mut self) {
+        self.route.clear();
+        self.step = 0;
+        self.wait.reset();
+        self.state = FerryState::Docked;
+    }
+}
+
+// Places used on a ferry
+#[derive(Component)]
+struct FerryLabel;
+
+// ·······
+// Systems
+// ·······
+
+// Put a ferry on the selected start, or take back an empty one that is docked there
+fn place_ferry(
+    mut cmd: Commands,
+    input: Res<Input<Bind>>,
+    keybinds: Res<Persistent<Keybinds>>,
+    assets: Res<StartAssets>,
+    mut shop: ResMut<Shop>,
+    selected: Query<&TilePos, (With<SelectedTile>, With<StartTile>)>,
+    ferries: Query<(Entity, &Ferry)>,
+    tilemap: Query<(&TilemapLayer, &TilemapGridSize, &TilemapType, &Transform)>,
+) {
+    if !keybinds
+        .place_ferry
+        .iter()
+        .any(|bind| input.just_pressed(*bind))
+    {
+        return;
+    }
+    let Ok(pos) = selected.get_single() else {
+        return;
+    };
+
+    if let Some((entity, ferry)) = ferries.iter().find(|(_, ferry)| ferry.dock == *pos) {
+        if ferry.state == FerryState::Docked && ferry.passengers.is_empty() {
+            cmd.entity(entity).despawn_recursive();
+            shop.ferries += 1;
+        }
+        return;
+    }
+
+    if shop.ferries == 0 {
+        return;
+    }
+    let Some((_, grid_size, map_type, map_trans)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+    shop.ferries -= 1;
+
+    let world = tile_to_pos(pos, grid_size, map_type, map_trans);
+    cmd.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: FERRY_COLOR,
+                custom_size: Some(FERRY_SIZE),
+                ..default()
+            },
+            transform: Transform::from_translation(world.extend(6.)),
+            ..default()
+        },
+        Ferry::new(*pos),
+    ))
+    .with_children(|ferry| {
+        ferry.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 28.,
+                        color: Color::WHITE,
+                    },
+                ),
+                transform: Transform::from_xyz(0., 0., 1.),
+                ..default()
+            },
+            FerryLabel,
+        ));
+    });
+}
+
+// Docked ferries take the spirits waiting on their start, if there is a way to an end for them
+fn board_ferry(
+    mut cmd: Commands,
+    shop: Res<Shop>,
+    mut ferries: Query<&mut Ferry>,
+    spirits: Query<(Entity, &Spirit, &SpiritKind, Option<&Affinity>)>,
+    mut paths: ParamSet<(
+        Query<(
+            &PathTile,
+            Option<&StartTile>,
+            Option<&EndTile>,
+            Option<&Affinity>,
+        )>,
+        Query<&mut PathTile>,
+    )>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType, &TileStorage)>,
+) {
+    let Some((_, map_size, map_type, storage)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    let mut boarded = Vec::new();
+    for mut ferry in ferries.iter_mut() {
+        for (entity, spirit, kind, affinity) in spirits.iter() {
+            if spirit.waiting_on() != Some(ferry.dock)
+                || !ferry.has_room(*kind, affinity, shop.ferry_level)
+            {
+                continue;
+            }
+            let route = find_route(
+                ferry.dock,
+                affinity,
+                map_size,
+                map_type,
+                storage,
+                &paths.p0(),
+            );
+            if route.is_none() {
+                continue;
+            }
+            ferry.passengers.push((*kind, affinity.copied()));
+            cmd.entity(entity).despawn_recursive();
+            boarded.push((ferry.dock, kind.weight()));
+        }
+    }
+
+    // Free the places they had on the start
+    let mut paths = paths.p1();
+    for (dock, weight) in boarded {
+        if let Some(mut path) = storage
+            .get(&dock)
+            .and_then(|entity| paths.get_mut(entity).ok())
+        {
+            path.count = path.count.saturating_sub(weight);
+        }
+    }
+}
+
+// Ferries leave when they are full or have waited long enough and there is a way to an end
+fn depart_ferry(
+    time: Res<Time>,
+    shop: Res<Shop>,
+    mut ferries: Query<&mut Ferry>,
+    paths: Query<(
+        &PathTile,
+        Option<&StartTile>,
+        Option<&EndTile>,
+        Option<&Affinity>,
+    )>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType, &TileStorage)>,
+) {
+    let Some((_, map_size, map_type, storage)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    for mut ferry in ferries.iter_mut() {
+        if ferry.state != FerryState::Docked || ferry.passengers.is_empty() {
+            continue;
+        }
+        let full = ferry.load() >= capacity(shop.ferry_level);
+        if !ferry.wait.tick(time.delta()).finished() && !full {
+            continue;
+        }
+
+        let affinity = ferry.passengers.first().and_then(|(_, affinity)| *affinity);
+        let Some(route) = find_route(
+            ferry.dock,
+            affinity.as_ref(),
+            map_size,
+            map_type,
+            storage,
+            &paths,
+        ) else {
+            continue;
+        };
+        ferry.route = route;
+        ferry.step = 1;
+        ferry.state = FerryState::Sailing;
+    }
+}
+
+fn sail_ferry(
+    mut cmd: Commands,
+    time: Res<Time>,
+    shop: Res<Shop>,
+    spirit_assets: Res<SpiritAssets>,
+    sprites: Res<Assets<Aseprite>>,
+    mut ferries: Query<(&mut Ferry, &mut Transform)>,
+    mut starts: Query<(&TilePos, &mut StartTile)>,
+    mut ends: Query<&mut PathTile, With<EndTile>>,
+    paths: Query<(), With<PathTile>>,
+    tilemap: Query<
+        (
+            &TilemapLayer,
+            &TilemapGridSize,
+            &TilemapType,
+            &TileStorage,
+            &Transform,
+        ),
+        Without<Ferry>,
+    >,
+) {
+    let Some((_, grid_size, map_type, storage, map_trans)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+    let Some(sprite) = sprites.get(&spirit_assets.stix) else {
+        return;
+    };
+    let speed = FERRY_SPEED * (1. + shop.ferry_level as f32 * SPEED_STEP) * shop.speed();
+
+    for (mut ferry, mut trans) in ferries.iter_mut() {
+        let Some(target) = ferry.route.get(ferry.step).copied() else {
+            continue;
+        };
+
+        // The river was erased in front of it, the passengers are lost and it goes back
+        let erased = storage
+            .get(&target)
+            .map_or(true, |entity| paths.get(entity).is_err());
+        if ferry.state == FerryState::Sailing && erased {
+            let lost = ferry.passengers.len() as f32;
+            ferry.passengers.clear();
+            for (pos, mut start) in starts.iter_mut() {
+                if *pos == ferry.dock {
+                    start.lose_counter += 2. * lost;
+                }
+            }
+            ferry.turn_back();
+            continue;
+        }
+
+        let goal = tile_to_pos(&target, grid_size, map_type, map_trans);
+        let delta = goal - trans.translation.xy();
+        let step = speed * time.delta_seconds();
+        if delta.length() > step {
+            trans.translation += (delta.normalize() * step).extend(0.);
+            continue;
+        }
+        trans.translation = goal.extend(trans.translation.z);
+        ferry.step += 1;
+        if ferry.step < ferry.route.len() {
+            continue;
+        }
+
+        match ferry.state {
+            // Passengers get off and wait for the end to take them, like spirits that walk there
+            FerryState::Sailing => {
+                let dock = ferry.dock;
+                for (kind, affinity) in ferry.passengers.drain(..) {
+                    if let Some(mut end) = storage
+                        .get(&target)
+                        .and_then(|entity| ends.get_mut(entity).ok())
+                    {
+                        end.count += kind.weight();
+                    }
+                    spawn_spirit_at(
+                        &mut cmd,
+                        sprite,
+                        &spirit_assets,
+                        dock,
+                        target,
+                        goal,
+                        kind,
+                        affinity.as_ref(),
+                    );
+                }
+                ferry.turn_back();
+            }
+            FerryState::Returning => ferry.moor(),
+            FerryState::Docked => {}
+        }
+    }
+}
+
+fn update_labels(
+    shop: Res<Shop>,
+    ferries: Query<(&Ferry, &Children)>,
+    mut labels: Query<&mut Text, With<FerryLabel>>,
+) {
+    let capacity = capacity(shop.ferry_level);
+    for (ferry, children) in ferries.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = labels.get_mut(*child) {
+                text.sections[0].value = format!("{}/{}", ferry.load(), capacity);
+            }
+        }
+    }
+}
+
+fn reset_ferries(mut cmd: Commands, ferries: Query<Entity, With<Ferry>>) {
+    for entity in ferries.iter() {
+        cmd.entity(entity).despawn_recursive();
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+pub fn capacity(level: u32) -> u32 {
+    FERRY_CAPACITY + level * CAPACITY_STEP
+}
+
+// Shortest way through the rivers to an end that accepts the passengers
+// Ferries follow the currents but can cross bridges in any direction
+fn find_route(
+    dock: TilePos,
+    affinity: Option<&Affinity>,
+    map_size: &TilemapSize,
+    map_type: &TilemapType,
+    storage: &TileStorage,
+    paths: &Query<(
+        &PathTile,
+        Option<&StartTile>,
+        Option<&EndTile>,
+        Option<&Affinity>,
+    )>,
+) -> Option<Vec<TilePos>> {
+    let mut prev = HashMap::from([(dock, dock)]);
+    let mut queue = VecDeque::from([dock]);
+
+    while let Some(pos) = queue.pop_front() {
+        let Some((path, _, end, end_affinity)) =
+            storage.get(&pos).and_then(|entity| paths.get(entity).ok())
+        else {
+            continue;
+        };
+
+        // Ferries stop at the first end they reach, and only unload where they are accepted
+        if end.is_some() {
+            if !Affinity::matches(affinity, end_affinity) {
+                continue;
+            }
+            let mut route = vec![pos];
+            let mut curr = pos;
+            while curr != dock {
+                curr = prev[&curr];
+                route.push(curr);
+            }
+            route.reverse();
+            return Some(route);
+        }
+
+        for next in get_neighbours(&pos, map_size, map_type) {
+            if prev.contains_key(&next) {
+                continue;
+            }
+            let Some((next_path, start, ..)) =
+                storage.get(&next).and_then(|entity| paths.get(entity).ok())
+            else {
+                continue;
+            };
+            if start.is_some() || !can_flow(&pos, path.current, &next, next_path.current) {
+                continue;
+            }
+            prev.insert(next, pos);
+            queue.push_back(next);
+        }
+    }
+    None
+}
@@ -0,0 +1,199 @@
+#![allow(clippy::too_many_arguments)]
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+
+use crate::{
+    game::GameCam,
+    modifiers::{Modifier, RunModifiers},
+    spirits::Spirit,
+    tilemap::{
+        play_to_real_size, tile_distance, tile_to_pos, LevelSize, PathTile, StartTile, TilemapLayer,
+    },
+    ui::*,
+    GameState,
+};
+
+// Tiles around rivers and ends that are revealed
+const RIVER_SIGHT: u32 = 2;
+// Tiles around spirits that are revealed
+const SPIRIT_SIGHT: u32 = 1;
+// Distance from the edge of the screen to the markers of hidden starts
+const MARKER_MARGIN: f32 = 24.;
+
+// ······
+// Plugin
+// ······
+
+// Fog of war modifier, the level is only known near the rivers and spirits
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            init_fog.run_if(in_state(GameState::Play).and_then(resource_added::<LevelSize>())),
+        )
+        .add_systems(
+            Update,
+            (reveal_fog, update_markers)
+                .chain()
+                .run_if(in_state(GameState::Play).and_then(resource_exists::<Fog>())),
+        )
+        .add_systems(OnExit(GameState::Play), clear_markers)
+        .add_systems(OnEnter(GameState::End), clear_fog);
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Tiles that have been seen at least once this run
+#[derive(Resource, Default)]
+pub struct Fog {
+    pub revealed: HashSet<TilePos>,
+}
+
+// ··········
+// Components
+// ··········
+
+// Indicator for a start that is hidden in the fog
+#[derive(Component)]
+struct FogMarker(TilePos);
+
+// ·······
+// Systems
+// ·······
+
+// The initial level is known, everything that is added when it grows starts hidden
+fn init_fog(mut cmd: Commands, modifiers: Res<RunModifiers>, level_size: Res<LevelSize>) {
+    if !modifiers.has(Modifier::Fog) {
+        cmd.remove_resource::<Fog>();
+        return;
+    }
+
+    let (offset, size) = play_to_real_size(&level_size);
+    let mut fog = Fog::default();
+    for x in offset.x..offset.x + size.x {
+        for y in offset.y..offset.y + size.y {
+            fog.revealed.insert(TilePos { x, y });
+        }
+    }
+    cmd.insert_resource(fog);
+}
+
+fn reveal_fog(
+    mut fog: ResMut<Fog>,
+    level_size: Res<LevelSize>,
+    tilemap: Query<&TilemapType>,
+    rivers: Query<(&TilePos, Option<&StartTile>), With<PathTile>>,
+    spirits: Query<&Spirit>,
+) {
+    let Some(map_type) = tilemap.iter().next() else {
+        return;
+    };
+
+    // Starts only light up their surroundings once they are connected
+    let rivers = rivers
+        .iter()
+        .filter(|(_, start)| start.map_or(true, |start| start.connected))
+        .map(|(pos, _)| (*pos, RIVER_SIGHT));
+    let spirits = spirits.iter().map(|spirit| (spirit.tile(), SPIRIT_SIGHT));
+
+    let (offset, size) = play_to_real_size(&level_size);
+    for (pos, sight) in rivers.chain(spirits) {
+        let min_x = pos.x.saturating_sub(sight).max(offset.x);
+        let min_y = pos.y.saturating_sub(sight).max(offset.y);
+        let max_x = (pos.x + sight).min(offset.x + size.x - 1);
+        let max_y = (pos.y + sight).min(offset.y + size.y - 1);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let other = TilePos { x, y };
+                if tile_distance(&pos, &other, map_type) <= sight {
+                    fog.revealed.insert(other);
+                }
+            }
+        }
+    }
+}
+
+// Point towards the starts hidden in the fog, keeping the markers inside of the screen
+fn update_markers(
+    mut cmd: Commands,
+    fog: Res<Fog>,
+    style: Res<UIStyle>,
+    starts: Query<&TilePos, With<StartTile>>,
+    tilemap: Query<(&TilemapLayer, &TilemapGridSize, &TilemapType, &Transform)>,
+    cam: Query<(&Camera, &GlobalTransform), With<GameCam>>,
+    mut markers: Query<(Entity, &FogMarker, &mut Style)>,
+    node: Query<Entity, With<UiNode>>,
+) {
+    let hidden = starts
+        .iter()
+        .filter(|pos| !fog.revealed.contains(*pos))
+        .copied()
+        .collect::<Vec<_>>();
+
+    for (entity, marker, _) in markers.iter() {
+        if !hidden.contains(&marker.0) {
+            cmd.entity(entity).despawn_recursive();
+        }
+    }
+    let missing = hidden
+        .iter()
+        .filter(|pos| !markers.iter().any(|(_, marker, _)| marker.0 == **pos))
+        .collect::<Vec<_>>();
+    if let Some(mut node) = node.get_single().ok().and_then(|node| cmd.get_entity(node)) {
+        node.with_children(|parent| {
+            for pos in missing {
+                UIText::new(&style, "!", Some(FogMarker(*pos)))
+                    .with_title()
+                    .with_style(Style {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    })
+                    .add(parent);
+            }
+        });
+    }
+
+    let Ok((cam, cam_trans)) = cam.get_single() else {
+        return;
+    };
+    let Some(screen) = cam.logical_viewport_size() else {
+        return;
+    };
+    let Some((_, grid_size, map_type, map_trans)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    for (_, marker, mut layout) in markers.iter_mut() {
+        let world = tile_to_pos(&marker.0, grid_size, map_type, map_trans);
+        let Some(pos) = cam.world_to_viewport(cam_trans, world.extend(0.)) else {
+            continue;
+        };
+        let pos = pos.clamp(
+            Vec2::splat(MARKER_MARGIN),
+            (screen - MARKER_MARGIN).max(Vec2::splat(MARKER_MARGIN)),
+        );
+        layout.left = Val::Px(pos.x);
+        layout.top = Val::Px(pos.y);
+    }
+}
+
+fn clear_markers(mut cmd: Commands, markers: Query<Entity, With<FogMarker>>) {
+    for entity in markers.iter() {
+        cmd.entity(entity).despawn_recursive();
+    }
+}
+
+fn clear_fog(mut cmd: Commands) {
+    cmd.remove_resource::<Fog>();
+}
@@ -1,16 +1,26 @@
 #![allow(clippy::too_many_arguments)]
 
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
 use bevy::{prelude::*, render::view::RenderLayers, window::WindowResized};
 use bevy_ecs_tilemap::prelude::*;
 use bevy_persistent::Persistent;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::{
-    config::GameScore,
+    attract::Attract,
+    config::{GameOptions, GameScore, RunRecord, HISTORY_LEN},
+    daily::Daily,
+    level::CurrentLevel,
     load::StartAssets,
+    modifiers::{Modifier, RunModifiers},
     tilemap::{
-        play_to_real_size, tile_to_pos, EndTile, ForegroundTile, LevelSize, PathTile, StartTile,
-        TilemapLayer, TilesAvailable, MAP_SIZE,
+        get_neighbours, insert_start_end, play_to_real_size, tile_distance, tile_in_level,
+        tile_to_pos, Affinity, EndTile, LevelSize, NeedsReset, PathTile, StartTile, Terrain,
+        TilemapLayer, TilesAvailable, AFFINITY_COLORS, MAP_SIZE,
     },
     ui::*,
     GameState, INITIAL_RESOLUTION,
@@ -24,24 +34,64 @@ const START_SCORES: [u32; 20] = [
 
 const END_SCORES: [u32; 7] = [0, 70, 200, 350, 600, 1000, 3000];
 
+// Chances of terrain appearing in the new tiles when the level grows
+const ROCK_CHANCE: f64 = 0.08;
+const MARSH_CHANCE: f64 = 0.08;
+const RIVER_CHANCE: f64 = 0.5;
+
+// Zen runs need this many times the score for each new start and end
+const ZEN_SPAWN_SCALE: u32 = 2;
+// Length of a time attack run
+const TIME_ATTACK_SECONDS: f32 = 180.;
+
+// Tiles given to the player for each new start and end
+const START_BONUS: u32 = 2;
+const END_BONUS: u32 = 4;
+
+// Past this zoom the camera pans instead, moving when the mouse is close to the edges
+const MAX_ZOOM: f32 = 2.4;
+const EDGE_PAN: f32 = 0.06;
+const PAN_SPEED: f32 = 900.;
+
 pub struct CharonPlugin;
 
 impl Plugin for CharonPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(SpawnedCount::default())
-            .add_systems(OnEnter(GameState::Play), init_game)
+            .init_resource::<GameMode>()
+            .init_resource::<RunRng>()
+            .add_systems(OnEnter(GameState::Play), (init_camera, init_game))
+            .add_systems(OnEnter(GameState::Editor), init_camera)
             .add_systems(
                 Update,
                 (
                     zoom_camera,
+                    fit_camera.run_if(resource_exists_and_changed::<LevelSize>()),
+                    pan_camera.run_if(resource_exists::<LevelSize>()),
+                )
+                    .run_if(in_state(GameState::Play).or_else(in_state(GameState::Editor))),
+            )
+            .add_systems(
+                Update,
+                (
+                    init_layout
+                        .run_if(resource_exists::<Daily>().and_then(resource_added::<LevelSize>())),
                     spawn_start_end.run_if(
                         resource_exists::<TilesAvailable>()
-                            .and_then(resource_exists_and_changed::<Persistent<GameScore>>()),
+                            .and_then(resource_exists_and_changed::<Persistent<GameScore>>())
+                            .and_then(not(resource_exists::<CurrentLevel>())),
                     ),
                 )
+                    .chain()
                     .run_if(in_state(GameState::Play)),
             )
+            .add_systems(
+                Update,
+                tick_run_timer
+                    .run_if(in_state(GameState::Play).and_then(resource_exists::<RunTimer>())),
+            )
             .add_systems(OnExit(GameState::Play), pause_game)
+            .add_systems(OnExit(GameState::Editor), pause_game)
             .add_systems(OnEnter(GameState::End), reset_score);
     }
 }
@@ -56,6 +106,49 @@ struct SpawnedCount {
     end: usize,
 }
 
+// Rules of the current run, chosen in the menu
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameMode {
+    // Play until a start waits for too long
+    #[default]
+    Endless,
+    // No losing and slower spawns
+    Zen,
+    // Score as much as possible before the time runs out
+    TimeAttack,
+    // Fixed layout and tiles, deliver enough spirits to win
+    Puzzle,
+    // Same seed, rules and starting layout for everyone on the same day, only one try
+    Daily,
+}
+
+impl GameMode {
+    pub fn name(&self) -> &str {
+        match self {
+            GameMode::Endless => "Endless",
+            GameMode::Zen => "Zen",
+            GameMode::TimeAttack => "Time attack",
+            GameMode::Puzzle => "Puzzle",
+            GameMode::Daily => "Daily",
+        }
+    }
+}
+
+// Time left in a time attack run
+#[derive(Resource)]
+pub struct RunTimer(pub Timer);
+
+// Random numbers for the level generation of the current run, seeded for the daily challenge
+// The starting layout only depends on the seed, but later spawns also depend on the rivers built
+#[derive(Resource)]
+pub struct RunRng(pub StdRng);
+
+impl Default for RunRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
 // ··········
 // Components
 // ··········
@@ -78,8 +171,97 @@ pub struct InitialText;
 fn init_game(
     mut cmd: Commands,
     mut score: ResMut<Persistent<GameScore>>,
-    mut cam: Query<&mut Camera, With<GameCam>>,
+    opts: Res<Persistent<GameOptions>>,
+    mode: Res<GameMode>,
+    needs_reset: Option<Res<NeedsReset>>,
+    level: Option<Res<CurrentLevel>>,
+    attract: Option<Res<Attract>>,
+) {
+    score.score = 0;
+    score.delivered = 0;
+
+    // The timer and the seed are kept when coming back from the menu
+    if needs_reset.is_some() {
+        if *mode == GameMode::TimeAttack {
+            cmd.insert_resource(RunTimer(Timer::from_seconds(
+                TIME_ATTACK_SECONDS,
+                TimerMode::Once,
+            )));
+        } else {
+            cmd.remove_resource::<RunTimer>();
+        }
+
+        if *mode == GameMode::Daily {
+            let daily = Daily::today();
+            cmd.insert_resource(RunRng(StdRng::seed_from_u64(daily.seed)));
+            cmd.insert_resource(daily);
+            // Starting the challenge is enough to use today's try
+            score
+                .update(|score| {
+                    if score.last_daily != daily.day {
+                        score.daily_score = 0;
+                    }
+                    score.last_daily = daily.day;
+                })
+                .expect("Failed to update score");
+        } else {
+            cmd.insert_resource(RunRng::default());
+            cmd.remove_resource::<Daily>();
+        }
+
+        // Modifiers are only picked before endless runs, other modes and levels have fixed rules
+        let modifiers = if level.is_none() && attract.is_none() && *mode == GameMode::Endless {
+            opts.modifiers.clone()
+        } else {
+            Vec::new()
+        };
+        cmd.insert_resource(RunModifiers::new(modifiers));
+    }
+}
+
+// Starting terrain and tiles of the daily challenge
+// The middle row is kept clear so the first start and end can always be connected
+fn init_layout(
+    mut cmd: Commands,
+    daily: Res<Daily>,
+    mut rng: ResMut<RunRng>,
+    level_size: Res<LevelSize>,
+    mut available: ResMut<TilesAvailable>,
+    tilemap: Query<(&TilemapLayer, &TileStorage)>,
 ) {
+    let (offset, size) = play_to_real_size(&level_size);
+    let mut terrain = generate_terrain(
+        &LevelSize(TilemapSize { x: 0, y: 0 }),
+        &level_size,
+        daily.terrain,
+        &mut rng.0,
+    );
+    terrain.retain(|(pos, _)| pos.y != offset.y + size.y / 2);
+
+    for (layer, storage) in tilemap.iter() {
+        if !matches!(layer, TilemapLayer::Background | TilemapLayer::RiverStix) {
+            continue;
+        }
+        for (pos, terrain) in terrain.iter() {
+            if let Some(entity) = storage.get(pos) {
+                cmd.entity(entity).insert(*terrain);
+            }
+        }
+    }
+    available.0 = daily.tiles;
+}
+
+fn tick_run_timer(
+    time: Res<Time>,
+    mut timer: ResMut<RunTimer>,
+    mut state: ResMut<NextState<GameState>>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        state.set(GameState::End);
+    }
+}
+
+fn init_camera(mut cmd: Commands, mut cam: Query<&mut Camera, With<GameCam>>) {
     if cam.iter().count() == 0 {
         cmd.spawn((
             Camera2dBundle::default(),
@@ -91,8 +273,6 @@ fn init_game(
     for mut cam in cam.iter_mut() {
         cam.is_active = true;
     }
-
-    score.score = 0;
 }
 
 fn pause_game(mut cam: Query<&mut Camera, With<GameCam>>) {
@@ -105,12 +285,37 @@ fn reset_score(
     mut score: ResMut<Persistent<GameScore>>,
     mut count: ResMut<SpawnedCount>,
     mut cam: Query<&mut GameCam>,
+    level: Option<Res<CurrentLevel>>,
+    attract: Option<Res<Attract>>,
+    mode: Res<GameMode>,
+    modifiers: Res<RunModifiers>,
 ) {
     score
         .update(|score| {
             score.last_score = score.score;
-            score.best_score = score.score.max(score.best_score);
+            // Levels and demo runs don't count towards the best scores
+            if level.is_none() && attract.is_none() {
+                if *mode == GameMode::Daily {
+                    score.daily_score = score.score.max(score.daily_score);
+                }
+                let best = match *mode {
+                    GameMode::Zen => &mut score.best_zen,
+                    GameMode::TimeAttack => &mut score.best_time_attack,
+                    GameMode::Daily => &mut score.best_daily,
+                    _ => &mut score.best_score,
+                };
+                *best = score.score.max(*best);
+
+                score.history.push(RunRecord {
+                    mode: mode.name().to_string(),
+                    score: score.score,
+                    modifiers: modifiers.list.clone(),
+                });
+                let extra = score.history.len().saturating_sub(HISTORY_LEN);
+                score.history.drain(..extra);
+            }
             score.score = 0;
+            score.delivered = 0;
         })
         .expect("Failed to update score");
 
@@ -124,6 +329,11 @@ fn reset_score(
 fn spawn_start_end(
     mut cmd: Commands,
     score: Res<Persistent<GameScore>>,
+    opts: Res<Persistent<GameOptions>>,
+    mode: Res<GameMode>,
+    daily: Option<Res<Daily>>,
+    modifiers: Res<RunModifiers>,
+    mut rng: ResMut<RunRng>,
     assets: Res<StartAssets>,
     mut level_size: ResMut<LevelSize>,
     mut available: ResMut<TilesAvailable>,
@@ -135,10 +345,11 @@ fn spawn_start_end(
         &TileStorage,
         &Transform,
     )>,
-    starts: Query<&TilePos, With<StartTile>>,
-    ends: Query<&TilePos, With<EndTile>>,
+    starts: Query<(&TilePos, Option<&Affinity>), With<StartTile>>,
+    ends: Query<(&TilePos, Option<&Affinity>), With<EndTile>>,
+    terrain: Query<(&TilePos, &Terrain)>,
+    paths: Query<&TilePos, With<PathTile>>,
     mut visible: Query<&mut TileVisible>,
-    mut cam: Query<&mut GameCam>,
     tutorial: Query<Entity, With<TutorialText>>,
     story_text: Query<Entity, With<InitialText>>,
     style: Res<UIStyle>,
@@ -151,11 +362,7 @@ fn spawn_start_end(
     }
 
     // Check if we need to spawn a start or end tile
-    let next_start = if count.start < START_SCORES.len() {
-        START_SCORES[count.start]
-    } else {
-        5000 + (count.start + 1 - START_SCORES.len()) as u32 * 1000
-    };
+    let next_start = start_score(count.start);
 
     let next_end = if count.end < END_SCORES.len() {
         END_SCORES[count.end]
@@ -163,15 +370,33 @@ fn spawn_start_end(
         (count.end + 1 - END_SCORES.len()) as u32 * 10000
     };
 
+    // Percentage of the usual score needed, zen runs are slower and the daily challenge varies
+    let mut pace = match (*mode, &daily) {
+        (GameMode::Zen, _) => ZEN_SPAWN_SCALE * 100,
+        (_, Some(daily)) => daily.spawn_pace,
+        _ => 100,
+    };
+    if modifiers.has(Modifier::DoubleSpawns) {
+        pace /= 2;
+    }
+    let next_start = next_start * pace / 100;
+    let next_end = next_end * pace / 100;
+
+    // The daily challenge has its own rules instead of the options
+    let color_match = daily
+        .as_ref()
+        .map_or(opts.color_match, |daily| daily.color_match);
+    let density = daily.as_ref().map_or(1., |daily| daily.terrain);
+
     let mut is_start = false;
     let mut is_end = false;
 
-    if score.score >= next_start {
+    if score.delivered >= next_start {
         count.start += 1;
         is_start = true;
     }
 
-    if score.score >= next_end {
+    if score.delivered >= next_end {
         count.end += 1;
         is_end = true;
     }
@@ -181,38 +406,159 @@ fn spawn_start_end(
     };
 
     // Grow level size every 2 starts (only if we are not at the max size)
-    if is_start && (count.start + 3) % 4 == 0 && level_size.0.x < MAP_SIZE.x {
-        level_size.0.x += 2;
-        level_size.0.y += 2;
-        if let Ok(mut cam) = cam.get_single_mut() {
-            cam.target_zoom += 0.3;
-        }
+    let growth = !modifiers.has(Modifier::NoGrowth);
+    let mut new_terrain = Vec::new();
+    if growth && is_start && (count.start + 3) % 4 == 0 {
+        new_terrain = grow_level(&mut cmd, &mut level_size, &tilemap, density, &mut rng.0)
+            .unwrap_or_default();
     }
-    let (offset, size) = play_to_real_size(&level_size);
+    let map_type = tilemap
+        .iter()
+        .next()
+        .map(|(_, _, map_type, _, _)| *map_type)
+        .unwrap_or_default();
+
+    // Starts and ends are never placed over terrain
+    let mut terrain = terrain
+        .iter()
+        .map(|(pos, terrain)| (*pos, *terrain))
+        .chain(new_terrain)
+        .collect::<HashMap<_, _>>();
+    let paths = paths.iter().copied().collect::<HashSet<_>>();
+    // Starts and ends placed this frame are not in the queries yet
+    let mut starts = starts
+        .iter()
+        .map(|(pos, affinity)| (*pos, affinity.copied()))
+        .collect::<Vec<_>>();
+    let mut ends = ends
+        .iter()
+        .map(|(pos, affinity)| (*pos, affinity.copied()))
+        .collect::<Vec<_>>();
 
     let mut spawn_fun = |is_start: bool| {
+        // With color matching, ends cycle through the colors and starts pick one of the existing ones
+        let affinity = if !color_match {
+            None
+        } else if is_start {
+            let colors = count.end.clamp(1, AFFINITY_COLORS.len());
+            Some(Affinity(rng.0.gen_range(0..colors)))
+        } else {
+            Some(Affinity((count.end - 1) % AFFINITY_COLORS.len()))
+        };
+        let bonus = if is_start { START_BONUS } else { END_BONUS };
+        let bonus = if modifiers.has(Modifier::HalfTiles) {
+            bonus / 2
+        } else {
+            bonus
+        };
+
+        // The new tile has to reach one of the other kind that accepts its color
+        let targets = if is_start { &ends } else { &starts }
+            .iter()
+            .filter(|(_, other)| Affinity::matches(affinity.as_ref(), other.as_ref()))
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        let taken = starts
+            .iter()
+            .chain(ends.iter())
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+
         // Get spawn position
-        let spawn_pos = {
-            if is_start {
-                if count.start <= 1 {
-                    Some(TilePos {
-                        x: offset.x + 1,
-                        y: offset.y + size.y / 2,
-                    })
+        let (offset, size) = play_to_real_size(&level_size);
+        let first = if is_start {
+            count.start <= 1
+        } else {
+            count.end <= 1
+        };
+        let spawn_pos = if first {
+            Some(TilePos {
+                x: if is_start {
+                    offset.x + 1
                 } else {
-                    get_spawn_pos(&offset, &size, &starts, &ends)
+                    offset.x + size.x - 2
+                },
+                y: offset.y + size.y / 2,
+            })
+        } else {
+            let budget = available.0 + bonus;
+            let mut found = get_spawn_pos(
+                &level_size,
+                &map_type,
+                &taken,
+                &targets,
+                &terrain,
+                &paths,
+                budget,
+                &mut rng.0,
+            );
+
+            // If it can't be connected with the tiles available, grow the level and try again
+            if growth && found.map_or(true, |(_, cost)| cost > budget) {
+                if let Some(grown) =
+                    grow_level(&mut cmd, &mut level_size, &tilemap, density, &mut rng.0)
+                {
+                    terrain.extend(grown);
+                    found = get_spawn_pos(
+                        &level_size,
+                        &map_type,
+                        &taken,
+                        &targets,
+                        &terrain,
+                        &paths,
+                        budget,
+                        &mut rng.0,
+                    )
+                    .or(found);
                 }
-            } else if count.end <= 1 {
-                Some(TilePos {
-                    x: offset.x + size.x - 2,
-                    y: offset.y + size.y / 2,
-                })
-            } else {
-                get_spawn_pos(&offset, &size, &starts, &ends)
             }
+
+            // As a last resort, give the tiles that are missing to connect it
+            if let Some((_, cost)) = found {
+                available.0 += cost.saturating_sub(budget);
+            }
+            found.map(|(pos, _)| pos)
         };
 
         if let Some(pos) = spawn_pos {
+            // With mirrored spawns, a twin appears on the opposite side if it is free and can be connected
+            let twin = TilePos {
+                x: 2 * offset.x + size.x - 1 - pos.x,
+                y: 2 * offset.y + size.y - 1 - pos.y,
+            };
+            let blocked = [taken.as_slice(), &[pos]].concat();
+            let mirrored = modifiers.has(Modifier::MirroredSpawns)
+                && !first
+                && !terrain.contains_key(&twin)
+                && !paths.contains(&twin)
+                && blocked
+                    .iter()
+                    .all(|p| tile_distance(p, &twin, &map_type) > 2);
+            let twin_cost = if !mirrored {
+                None
+            } else if targets.is_empty() {
+                Some(0)
+            } else {
+                connection_costs(&targets, &blocked, &terrain, &paths, &level_size, &map_type)
+                    .get(&twin)
+                    .copied()
+            };
+            if let Some(cost) = twin_cost {
+                available.0 += cost.saturating_sub(available.0 + bonus * 2);
+            }
+            let placed = [Some(pos), twin_cost.map(|_| twin)]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+
+            for pos in placed.iter() {
+                if is_start {
+                    starts.push((*pos, affinity));
+                } else {
+                    ends.push((*pos, affinity));
+                }
+            }
+
             // Add the story text (between 10 and 30 entities)
             if count.start == 2 {
                 cmd.spawn((
@@ -247,73 +593,50 @@ fn spawn_start_end(
             }
 
             for (layer, grid_size, map_type, storage, trans) in tilemap.iter() {
-                match layer {
-                    // Insert the logical tile in the river
-                    TilemapLayer::RiverStix => {
-                        if let Some(entity) = storage.get(&pos) {
-                            // Also generate tutorial text
-                            let world_pos = (tile_to_pos(&pos, grid_size, map_type, trans)
-                                + Vec2::new(0., 96.))
-                            .extend(10.);
-                            let style = TextStyle {
-                                font: assets.font.clone(),
-                                font_size: 32.,
-                                color: Color::rgb(0.9, 0.9, 0.7),
-                            };
-
-                            if is_start {
-                                cmd.entity(entity)
-                                    .insert((StartTile::default(), PathTile::default()));
-
-                                if count.start == 1 {
-                                    cmd.spawn((
-                                        Text2dBundle {
-                                            text: Text::from_section(
-                                                "Draw from here",
-                                                style.clone(),
-                                            ),
-                                            transform: Transform::from_translation(world_pos),
-                                            ..default()
-                                        },
-                                        TutorialText,
-                                    ));
-                                }
-                            } else {
-                                cmd.entity(entity).insert((EndTile, PathTile::default()));
-
-                                if count.end == 1 {
-                                    cmd.spawn((
-                                        Text2dBundle {
-                                            text: Text::from_section("to here", style),
-                                            transform: Transform::from_translation(world_pos),
-                                            ..default()
-                                        },
-                                        TutorialText,
-                                    ));
-                                }
-                            }
-                            if let Ok(mut visible) = visible.get_mut(entity) {
-                                visible.0 = true;
-                            }
-                        }
-                    }
-                    // Add the graphics element to the foreground
-                    TilemapLayer::Foreground => {
-                        if let Some(entity) = storage.get(&pos) {
-                            cmd.entity(entity).insert(if is_start {
-                                ForegroundTile::Start
-                            } else {
-                                ForegroundTile::End
-                            });
-                            if let Ok(mut visible) = visible.get_mut(entity) {
-                                visible.0 = true;
-                            }
-                        }
-                    }
-                    _ => continue,
+                for pos in placed.iter() {
+                    insert_start_end(
+                        &mut cmd,
+                        pos,
+                        is_start.then(StartTile::default),
+                        affinity,
+                        layer,
+                        storage,
+                        &mut visible,
+                    );
                 }
+
+                // Also generate tutorial text
+                if !matches!(layer, TilemapLayer::RiverStix) {
+                    continue;
+                }
+                let world_pos = (tile_to_pos(&pos, grid_size, map_type, trans)
+                    + Vec2::new(0., 96.))
+                .extend(10.);
+                let style = TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 32.,
+                    color: Color::rgb(0.9, 0.9, 0.7),
+                };
+
+                let text = if is_start && count.start == 1 {
+                    "Draw from here"
+                } else if !is_start && count.end == 1 {
+                    "to here"
+                } else {
+                    continue;
+                };
+                cmd.spawn((
+                    Text2dBundle {
+                        text: Text::from_section(text, style),
+                        transform: Transform::from_translation(world_pos),
+                        ..default()
+                    },
+                    TutorialText,
+                ));
             }
-            available.0 += if is_start { 2 } else { 4 };
+            available.0 += bonus * placed.len() as u32;
+        } else {
+            warn!("No place left to spawn a new start or end");
         }
     };
 
@@ -325,6 +648,68 @@ fn spawn_start_end(
     }
 }
 
+// Zoom out so the whole level fits in the screen
+fn fit_camera(level_size: Res<LevelSize>, mut cam: Query<&mut GameCam>) {
+    let steps = ((level_size.0.x as f32 - 8.) / 2.).max((level_size.0.y as f32 - 3.) / 2.);
+    for mut cam in cam.iter_mut() {
+        cam.target_zoom = (steps.max(0.) * 0.3).min(MAX_ZOOM);
+    }
+}
+
+// Keep the camera inside of the level, panning if it doesn't fit on the screen
+fn pan_camera(
+    time: Res<Time>,
+    level_size: Res<LevelSize>,
+    win: Query<&Window>,
+    mut cam: Query<(&mut Transform, &OrthographicProjection), With<GameCam>>,
+    tilemap: Query<(&TilemapLayer, &TilemapGridSize, &TilemapType, &Transform), Without<GameCam>>,
+) {
+    let Ok(win) = win.get_single() else {
+        return;
+    };
+    let Ok((mut trans, proj)) = cam.get_single_mut() else {
+        return;
+    };
+    let Some((_, grid_size, map_type, map_trans)) = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    // Level bounds in world space
+    let (offset, size) = play_to_real_size(&level_size);
+    let corner = |x: u32, y: u32| tile_to_pos(&TilePos { x, y }, grid_size, map_type, map_trans);
+    let min = corner(offset.x, offset.y);
+    let max = corner(offset.x + size.x - 1, offset.y + size.y - 1);
+    let center = (min + max) / 2.;
+
+    // How far the camera can move from the center, leaving one tile of margin
+    let view = Vec2::new(win.width(), win.height()) * proj.scale;
+    let margin = Vec2::new(grid_size.x, grid_size.y);
+    let slack = ((max - min + margin * 2. - view) / 2.).max(Vec2::ZERO);
+
+    if let Some(cursor) = win.cursor_position() {
+        let edge = Vec2::new(win.width(), win.height()) * EDGE_PAN;
+        let mut dir = Vec2::ZERO;
+        if cursor.x < edge.x {
+            dir.x = -1.;
+        } else if cursor.x > win.width() - edge.x {
+            dir.x = 1.;
+        }
+        // The cursor position goes down the screen
+        if cursor.y < edge.y {
+            dir.y = 1.;
+        } else if cursor.y > win.height() - edge.y {
+            dir.y = -1.;
+        }
+        trans.translation += (dir * PAN_SPEED * proj.scale * time.delta_seconds()).extend(0.);
+    }
+
+    let pos = trans.translation.xy().clamp(center - slack, center + slack);
+    trans.translation = pos.extend(trans.translation.z);
+}
+
 fn zoom_camera(
     mut cam: Query<(&mut OrthographicProjection, &GameCam)>,
     mut win: Query<&mut Window>,
@@ -351,60 +736,291 @@ fn zoom_camera(
 // Extra
 // ·····
 
+// Score needed for each new start, they are the milestones of a run
+pub fn start_score(count: usize) -> u32 {
+    if count < START_SCORES.len() {
+        START_SCORES[count]
+    } else {
+        5000 + (count + 1 - START_SCORES.len()) as u32 * 1000
+    }
+}
+
+// Pick a free tile for a new start or end, along with the tiles needed to connect it
+// Border tiles are preferred, then the interior, and if none can be connected with the budget the cheapest one
 fn get_spawn_pos(
-    offset: &TilemapSize,
-    size: &TilemapSize,
-    starts: &Query<&TilePos, With<StartTile>>,
-    ends: &Query<&TilePos, With<EndTile>>,
-) -> Option<TilePos> {
-    // Calculate possible positions (along the border)
-    let mut possible = Vec::new();
-    for i in 0..size.x {
-        possible.push(TilePos { x: i, y: 0 });
-        possible.push(TilePos {
-            x: i,
-            y: size.y - 1,
-        });
-    }
-    for i in 0..size.y {
-        possible.push(TilePos { x: 0, y: i });
-        possible.push(TilePos {
-            x: size.x - 1,
-            y: i,
-        });
-    }
-
-    // Remove occupied starts and ends and their neighbours
-    for start in starts.iter() {
-        let pos = TilePos {
-            x: start.x - offset.x,
-            y: start.y - offset.y,
+    level_size: &LevelSize,
+    map_type: &TilemapType,
+    taken: &[TilePos],
+    targets: &[TilePos],
+    terrain: &HashMap<TilePos, Terrain>,
+    paths: &HashSet<TilePos>,
+    budget: u32,
+    rng: &mut impl Rng,
+) -> Option<(TilePos, u32)> {
+    let (offset, size) = play_to_real_size(level_size);
+
+    // Split the free positions in the border and the interior
+    let mut border = Vec::new();
+    let mut interior = Vec::new();
+    for x in offset.x..offset.x + size.x {
+        for y in offset.y..offset.y + size.y {
+            let pos = TilePos { x, y };
+            // Keep away from other starts and ends and never go over terrain
+            if taken.iter().any(|p| tile_distance(p, &pos, map_type) <= 2)
+                || terrain.contains_key(&pos)
+            {
+                continue;
+            }
+
+            let on_border = x == offset.x
+                || y == offset.y
+                || x == offset.x + size.x - 1
+                || y == offset.y + size.y - 1;
+            if on_border {
+                border.push(pos);
+            } else {
+                interior.push(pos);
+            }
+        }
+    }
+
+    // Nothing to connect to yet, any border tile works
+    if targets.is_empty() {
+        let possible = if border.is_empty() {
+            &interior
+        } else {
+            &border
         };
-        possible.retain(|p| tile_distance(p, &pos) > 2);
+        if possible.is_empty() {
+            return None;
+        }
+        return Some((possible[rng.gen_range(0..possible.len())], 0));
+    }
+
+    let costs = connection_costs(targets, taken, terrain, paths, level_size, map_type);
+    let mut cheapest: Option<(TilePos, u32)> = None;
+    for possible in [border, interior] {
+        let costs = possible
+            .into_iter()
+            .filter_map(|pos| costs.get(&pos).map(|cost| (pos, *cost)))
+            .collect::<Vec<_>>();
+
+        let affordable = costs
+            .iter()
+            .filter(|(_, cost)| *cost <= budget)
+            .collect::<Vec<_>>();
+        if !affordable.is_empty() {
+            return Some(*affordable[rng.gen_range(0..affordable.len())]);
+        }
+
+        for (pos, cost) in costs {
+            if cheapest.map_or(true, |(_, best)| cost < best) {
+                cheapest = Some((pos, cost));
+            }
+        }
+    }
+    cheapest
+}
+
+// Fewest tiles needed to build a river from each position to any of the targets
+// Existing rivers are free, rocks and other starts and ends have to be avoided
+fn connection_costs(
+    targets: &[TilePos],
+    blocked: &[TilePos],
+    terrain: &HashMap<TilePos, Terrain>,
+    paths: &HashSet<TilePos>,
+    level_size: &LevelSize,
+    map_type: &TilemapType,
+) -> HashMap<TilePos, u32> {
+    let mut costs = HashMap::new();
+    let mut open = BinaryHeap::new();
+
+    // The tiles next to a target are already connected
+    for target in targets {
+        for neighbour in get_neighbours(target, &MAP_SIZE, map_type) {
+            if tile_in_level(&neighbour, level_size) && !targets.contains(&neighbour) {
+                costs.insert(neighbour, 0);
+                open.push(Reverse((0, neighbour.x, neighbour.y)));
+            }
+        }
     }
-    for end in ends.iter() {
-        let pos = TilePos {
-            x: end.x - offset.x,
-            y: end.y - offset.y,
+
+    // Djikstra's algorithm going out from the targets
+    while let Some(Reverse((cost, x, y))) = open.pop() {
+        let pos = TilePos { x, y };
+        if cost > *costs.get(&pos).unwrap_or(&u32::MAX) || blocked.contains(&pos) {
+            continue;
+        }
+
+        // Going through this tile means building on it
+        let step = if paths.contains(&pos) {
+            0
+        } else {
+            let Some(step) = Terrain::cost(terrain.get(&pos)) else {
+                continue;
+            };
+            step
         };
-        possible.retain(|p| tile_distance(p, &pos) > 2);
+
+        for neighbour in get_neighbours(&pos, &MAP_SIZE, map_type) {
+            if !tile_in_level(&neighbour, level_size) || targets.contains(&neighbour) {
+                continue;
+            }
+            let next = cost + step;
+            if next < *costs.get(&neighbour).unwrap_or(&u32::MAX) {
+                costs.insert(neighbour, next);
+                open.push(Reverse((next, neighbour.x, neighbour.y)));
+            }
+        }
     }
-    if possible.is_empty() {
+    costs
+}
+
+// Make the level bigger and add terrain to the new tiles, unless it is already at the max size
+fn grow_level(
+    cmd: &mut Commands,
+    level_size: &mut LevelSize,
+    tilemap: &Query<(
+        &TilemapLayer,
+        &TilemapGridSize,
+        &TilemapType,
+        &TileStorage,
+        &Transform,
+    )>,
+    density: f64,
+    rng: &mut impl Rng,
+) -> Option<Vec<(TilePos, Terrain)>> {
+    if level_size.0.x >= MAP_SIZE.x {
         return None;
     }
 
-    // Select random position
-    let selected = possible[rand::thread_rng().gen_range(0..possible.len())];
-    Some(TilePos {
-        x: selected.x + offset.x,
-        y: selected.y + offset.y,
-    })
+    let old_size = LevelSize(level_size.0);
+    level_size.0.x += 2;
+    level_size.0.y += 2;
+
+    let new_terrain = generate_terrain(&old_size, level_size, density, rng);
+    for (layer, _, _, storage, _) in tilemap.iter() {
+        if !matches!(layer, TilemapLayer::Background | TilemapLayer::RiverStix) {
+            continue;
+        }
+        for (pos, terrain) in new_terrain.iter() {
+            if let Some(entity) = storage.get(pos) {
+                cmd.entity(entity).insert(*terrain);
+            }
+        }
+    }
+    Some(new_terrain)
 }
 
-fn tile_distance(a: &TilePos, b: &TilePos) -> u32 {
-    ((a.x as i32 - b.x as i32).abs() + (a.y as i32 - b.y as i32).abs()) as u32
+// Add random terrain to the tiles revealed when the level grows
+// The density multiplies the chances of rocks and marshes
+fn generate_terrain(
+    old_size: &LevelSize,
+    new_size: &LevelSize,
+    density: f64,
+    rng: &mut impl Rng,
+) -> Vec<(TilePos, Terrain)> {
+    let (offset, size) = play_to_real_size(new_size);
+    let mut terrain = Vec::new();
+
+    // A river segment along the top or bottom edge, sometimes with a bridge
+    let len = rng.gen_range(3..=5);
+    if size.x > len + 2 && rng.gen_bool(RIVER_CHANCE) {
+        let y = if rng.gen_bool(0.5) {
+            offset.y
+        } else {
+            offset.y + size.y - 1
+        };
+        let start = offset.x + rng.gen_range(1..size.x - len - 1);
+        let bridge = rng.gen_bool(0.5).then_some(start + len / 2);
+        for x in start..start + len {
+            let kind = if Some(x) == bridge {
+                Terrain::Bridge
+            } else {
+                Terrain::River
+            };
+            terrain.push((TilePos { x, y }, kind));
+        }
+    }
+
+    // Scattered rocks and marshes
+    for x in offset.x..offset.x + size.x {
+        for y in offset.y..offset.y + size.y {
+            let pos = TilePos { x, y };
+            if tile_in_level(&pos, old_size) || terrain.iter().any(|(p, _)| *p == pos) {
+                continue;
+            }
+
+            let roll = rng.gen::<f64>();
+            if roll < ROCK_CHANCE * density {
+                terrain.push((pos, Terrain::Rock));
+            } else if roll < (ROCK_CHANCE + MARSH_CHANCE) * density {
+                terrain.push((pos, Terrain::Marsh));
+            }
+        }
+    }
+
+    terrain
 }
 
 fn lerp(a: f32, b: f32, t: f32) -> f32 {
     a + (b - a) * t
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: TilemapSize = TilemapSize { x: 5, y: 3 };
+
+    // Position relative to the bottom left corner of the level
+    fn at(x: u32, y: u32) -> TilePos {
+        let (offset, _) = play_to_real_size(&LevelSize(LEVEL));
+        TilePos {
+            x: offset.x + x,
+            y: offset.y + y,
+        }
+    }
+
+    // Costs to connect each tile to an end on the right of the middle row
+    fn costs(terrain: &[((u32, u32), Terrain)], blocked: &[TilePos]) -> HashMap<TilePos, u32> {
+        let terrain = terrain
+            .iter()
+            .map(|((x, y), terrain)| (at(*x, *y), *terrain))
+            .collect();
+        connection_costs(
+            &[at(4, 1)],
+            blocked,
+            &terrain,
+            &HashSet::new(),
+            &LevelSize(LEVEL),
+            &TilemapType::default(),
+        )
+    }
+
+    #[test]
+    fn straight_line() {
+        let costs = costs(&[], &[]);
+        assert_eq!(costs.get(&at(3, 1)), Some(&0));
+        assert_eq!(costs.get(&at(0, 1)), Some(&3));
+    }
+
+    #[test]
+    fn marsh_costs_two() {
+        let costs = costs(&[((2, 1), Terrain::Marsh)], &[]);
+        assert_eq!(costs.get(&at(0, 1)), Some(&4));
+    }
+
+    #[test]
+    fn blocked_route() {
+        let rocks = [
+            ((2, 0), Terrain::Rock),
+            ((2, 1), Terrain::Rock),
+            ((2, 2), Terrain::Rock),
+        ];
+        assert!(!costs(&rocks, &[]).contains_key(&at(0, 1)));
+
+        let blocked = [at(2, 0), at(2, 1), at(2, 2)];
+        assert!(!costs(&[], &blocked).contains_key(&at(0, 1)));
+    }
+}
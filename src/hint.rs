@@ -0,0 +1,199 @@
+#![allow(clippy::too_many_arguments)]
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+
+use crate::{
+    bot::{connect, BoardState},
+    config::{GameOptions, GameScore, Keybinds, Persistent},
+    input::Bind,
+    shop::ShopOpen,
+    tilemap::{
+        direction_index, get_neighbours, ForegroundTile, NeedsReset, PathTile, StartTile,
+        TilemapLayer, MAP_SIZE,
+    },
+    GameState,
+};
+
+// Hints that can be used on each run
+const HINTS_PER_RUN: u32 = 3;
+// Seconds that the ghost river stays on screen
+const HINT_DURATION: f32 = 8.;
+
+// ······
+// Plugin
+// ······
+
+pub struct HintPlugin;
+
+impl Plugin for HintPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Hints::default())
+            .add_systems(
+                OnEnter(GameState::Play),
+                init_hints.run_if(resource_exists::<NeedsReset>()),
+            )
+            .add_systems(
+                Update,
+                (
+                    show_hint.run_if(not(resource_exists::<ShopOpen>())),
+                    clear_hint,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Play)),
+            )
+            .add_systems(OnEnter(GameState::End), init_hints);
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+#[derive(Resource)]
+pub struct Hints {
+    pub left: u32,
+    // Tiles of the ghost river that is being shown
+    tiles: Vec<TilePos>,
+    timer: Timer,
+}
+
+impl Default for Hints {
+    fn default() -> Self {
+        Self {
+            left: HINTS_PER_RUN,
+            tiles: Vec::new(),
+            timer: Timer::from_seconds(HINT_DURATION, TimerMode::Once),
+        }
+    }
+}
+
+// ·······
+// Systems
+// ·······
+
+fn init_hints(mut hints: ResMut<Hints>) {
+    *hints = Hints::default();
+}
+
+// Show the cheapest river that connects the start that is closest to losing
+fn show_hint(
+    mut cmd: Commands,
+    mut hints: ResMut<Hints>,
+    mut score: ResMut<Persistent<GameScore>>,
+    input: Res<Input<Bind>>,
+    keybinds: Res<Persistent<Keybinds>>,
+    opts: Res<Persistent<GameOptions>>,
+    board: BoardState,
+    starts: Query<(&TilePos, &StartTile)>,
+    tilemap: Query<(&TilemapLayer, &TilemapType, &TileStorage)>,
+    foreground: Query<&ForegroundTile>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    if !keybinds.hint.iter().any(|bind| input.just_pressed(*bind)) {
+        return;
+    }
+    if hints.left == 0 || !hints.tiles.is_empty() {
+        return;
+    }
+
+    let Some(start) = starts
+        .iter()
+        .filter(|(_, start)| !start.connected)
+        .max_by(|(_, a), (_, b)| a.lose_counter.total_cmp(&b.lose_counter))
+        .map(|(pos, _)| *pos)
+    else {
+        return;
+    };
+    let Some(board) = board.board() else {
+        return;
+    };
+    let Some((cost, tiles)) = connect(&board, &start) else {
+        return;
+    };
+    if cost > board.available {
+        return;
+    }
+
+    for (layer, map_type, storage) in tilemap.iter() {
+        if !matches!(layer, TilemapLayer::Foreground) {
+            continue;
+        }
+
+        // Join the ghost tiles with each other and with the rivers around them, like autotile does
+        for pos in tiles.iter() {
+            let mask = get_neighbours(pos, &MAP_SIZE, map_type)
+                .iter()
+                .filter(|n| *n == &start || tiles.contains(n) || board.paths.contains_key(n))
+                .filter_map(|n| direction_index(pos, n, map_type))
+                .fold(0, |mask, i| mask | 1 << i);
+
+            let Some(entity) = storage.get(pos) else {
+                continue;
+            };
+            if foreground.contains(entity) {
+                continue;
+            }
+            cmd.entity(entity).insert(ForegroundTile::Hint(mask));
+            if let Ok(mut visible) = visible.get_mut(entity) {
+                visible.0 = true;
+            }
+        }
+    }
+
+    hints.left -= 1;
+    hints.tiles = tiles;
+    hints.timer.reset();
+    score.score = score.score.saturating_sub(opts.hint_penalty);
+}
+
+// Remove the ghost once it is built or after a while
+fn clear_hint(
+    mut cmd: Commands,
+    time: Res<Time>,
+    mut hints: ResMut<Hints>,
+    tilemap: Query<(&TilemapLayer, &TileStorage)>,
+    paths: Query<&PathTile>,
+    foreground: Query<&ForegroundTile>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    if hints.tiles.is_empty() {
+        return;
+    }
+    let expired = hints.timer.tick(time.delta()).finished();
+
+    let river = tilemap
+        .iter()
+        .find(|(layer, _)| matches!(layer, TilemapLayer::RiverStix))
+        .map(|(_, storage)| storage);
+    let built = |pos: &TilePos| {
+        river
+            .and_then(|storage| storage.get(pos))
+            .map_or(false, |entity| paths.contains(entity))
+    };
+
+    let mut removed = Vec::new();
+    for (layer, storage) in tilemap.iter() {
+        if !matches!(layer, TilemapLayer::Foreground) {
+            continue;
+        }
+        for pos in hints.tiles.iter() {
+            if !expired && !built(pos) {
+                continue;
+            }
+            removed.push(*pos);
+
+            let Some(entity) = storage.get(pos) else {
+                continue;
+            };
+            if !matches!(foreground.get(entity), Ok(ForegroundTile::Hint(_))) {
+                continue;
+            }
+            cmd.entity(entity).remove::<ForegroundTile>();
+            if let Ok(mut visible) = visible.get_mut(entity) {
+                visible.0 = false;
+            }
+        }
+    }
+    hints.tiles.retain(|pos| !removed.contains(pos));
+}
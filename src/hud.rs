@@ -1,9 +1,20 @@
+#![allow(clippy::too_many_arguments)]
 #![allow(clippy::type_complexity)]
 
 use bevy::prelude::*;
 use bevy_persistent::Persistent;
 
-use crate::{config::GameScore, load::GameAssets, tilemap::TilesAvailable, ui::*, GameState};
+use crate::{
+    attract::Attract,
+    config::GameScore,
+    game::RunTimer,
+    hint::Hints,
+    load::GameAssets,
+    shop::Shop,
+    tilemap::{Disconnected, TilesAvailable, DISCONNECTED_COLOR},
+    ui::*,
+    GameState,
+};
 
 // ······
 // Plugin
@@ -13,14 +24,16 @@ pub struct HudPlugin;
 
 impl Plugin for HudPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Play), init_hud)
-            .add_systems(
-                Update,
-                update_hud.run_if(
-                    in_state(GameState::Play).and_then(resource_exists::<TilesAvailable>()),
-                ),
-            )
-            .add_systems(OnExit(GameState::Play), exit_hud);
+        app.add_systems(
+            OnEnter(GameState::Play),
+            init_hud.run_if(not(resource_exists::<Attract>())),
+        )
+        .add_systems(
+            Update,
+            update_hud
+                .run_if(in_state(GameState::Play).and_then(resource_exists::<TilesAvailable>())),
+        )
+        .add_systems(OnExit(GameState::Play), exit_hud);
     }
 }
 
@@ -31,9 +44,24 @@ impl Plugin for HudPlugin {
 #[derive(Component)]
 struct ScoreText;
 
+// Obols to spend in the shop, next to the coin
+#[derive(Component)]
+struct ObolsText;
+
 #[derive(Component)]
 struct TilesText;
 
+// Warning about the rivers and starts that are not connected to an end
+#[derive(Component)]
+struct DisconnectedText;
+
+#[derive(Component)]
+struct HintsText;
+
+// Time left in time attack runs
+#[derive(Component)]
+struct TimeText;
+
 // ·······
 // Systems
 // ·······
@@ -81,6 +109,20 @@ fn init_hud(
                         UIText::new(&style, "0", Some(TilesText)).add(tiles);
                     });
 
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(5.0),
+                            top: Val::Px(style.text.font_size * 1.5 + 10.),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|warning| {
+                        UIText::new(&style, "", Some(DisconnectedText)).add(warning);
+                    });
+
                 parent
                     .spawn(NodeBundle {
                         style: Style {
@@ -95,10 +137,10 @@ fn init_hud(
                         },
                         ..default()
                     })
-                    .with_children(|score| {
-                        UIText::new(&style, "0", Some(ScoreText)).add(score);
+                    .with_children(|obols| {
+                        UIText::new(&style, "0", Some(ObolsText)).add(obols);
 
-                        score.spawn((
+                        obols.spawn((
                             ImageBundle {
                                 image: UiImage {
                                     texture: game_assets.coin_icon.clone(),
@@ -113,6 +155,49 @@ fn init_hud(
                             UI_LAYER,
                         ));
                     });
+
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            right: Val::Px(5.0),
+                            top: Val::Px(style.text.font_size * 1.5 + 10.),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|score| {
+                        UIText::new(&style, "", Some(ScoreText)).add(score);
+                    });
+
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            right: Val::Px(5.0),
+                            top: Val::Px(style.text.font_size * 3. + 15.),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|hints| {
+                        UIText::new(&style, "", Some(HintsText)).add(hints);
+                    });
+
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            width: Val::Percent(100.),
+                            top: Val::Px(5.0),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|time| {
+                        UIText::new(&style, "", Some(TimeText)).add(time);
+                    });
             });
         }
     }
@@ -121,12 +206,57 @@ fn init_hud(
 fn update_hud(
     score: Res<Persistent<GameScore>>,
     tiles: Res<TilesAvailable>,
+    disconnected: Option<Res<Disconnected>>,
+    hints: Res<Hints>,
+    timer: Option<Res<RunTimer>>,
+    shop: Res<Shop>,
     style: Res<UIStyle>,
     mut score_text: Query<&mut Text, (With<ScoreText>, Without<TilesText>)>,
     mut tiles_text: Query<&mut Text, (With<TilesText>, Without<ScoreText>)>,
+    mut warning_text: Query<
+        &mut Text,
+        (
+            With<DisconnectedText>,
+            Without<ScoreText>,
+            Without<TilesText>,
+        ),
+    >,
+    mut hints_text: Query<
+        &mut Text,
+        (
+            With<HintsText>,
+            Without<DisconnectedText>,
+            Without<ScoreText>,
+            Without<TilesText>,
+        ),
+    >,
+    mut time_text: Query<
+        &mut Text,
+        (
+            With<TimeText>,
+            Without<HintsText>,
+            Without<DisconnectedText>,
+            Without<ScoreText>,
+            Without<TilesText>,
+        ),
+    >,
+    mut obols_text: Query<
+        &mut Text,
+        (
+            With<ObolsText>,
+            Without<TimeText>,
+            Without<HintsText>,
+            Without<DisconnectedText>,
+            Without<ScoreText>,
+            Without<TilesText>,
+        ),
+    >,
 ) {
     for mut text in score_text.iter_mut() {
-        text.sections[0].value = format!("{}", score.score);
+        text.sections[0].value = format!("Saved {}", score.score);
+    }
+    for mut text in obols_text.iter_mut() {
+        text.sections[0].value = format!("{}", shop.obols);
     }
     for mut text in tiles_text.iter_mut() {
         text.sections[0].value = format!("{}", tiles.0);
@@ -141,9 +271,56 @@ fn update_hud(
             style.text.font_size
         };
     }
+
+    if let Some(disconnected) = disconnected {
+        let mut parts = Vec::new();
+        match disconnected.starts {
+            0 => {}
+            1 => parts.push("1 start".to_string()),
+            n => parts.push(format!("{} starts", n)),
+        }
+        match disconnected.tiles {
+            0 => {}
+            1 => parts.push("1 tile".to_string()),
+            n => parts.push(format!("{} tiles", n)),
+        }
+
+        for mut text in warning_text.iter_mut() {
+            text.sections[0].value = if parts.is_empty() {
+                String::new()
+            } else {
+                format!("Not connected: {}", parts.join(", "))
+            };
+            text.sections[0].style.color = DISCONNECTED_COLOR;
+        }
+    }
+
+    for mut text in hints_text.iter_mut() {
+        text.sections[0].value = format!("Hints {}", hints.left);
+    }
+
+    for mut text in time_text.iter_mut() {
+        text.sections[0].value = timer.as_ref().map_or(String::new(), |timer| {
+            let left = timer.0.remaining_secs().ceil() as u32;
+            format!("{}:{:02}", left / 60, left % 60)
+        });
+    }
 }
 
-fn exit_hud(mut cmd: Commands, text: Query<Entity, Or<(With<ScoreText>, With<TilesText>)>>) {
+fn exit_hud(
+    mut cmd: Commands,
+    text: Query<
+        Entity,
+        Or<(
+            With<ScoreText>,
+            With<TilesText>,
+            With<DisconnectedText>,
+            With<HintsText>,
+            With<TimeText>,
+            With<ObolsText>,
+        )>,
+    >,
+) {
     for text in text.iter() {
         cmd.entity(text).despawn_recursive();
     }
@@ -28,7 +28,8 @@ impl Plugin for InputPlugin {
                     handle_input_mouse,
                     handle_input_gamepad,
                     handle_mouse_moved,
-                ),
+                )
+                    .in_set(InputSet),
             )
             .add_systems(PostUpdate, clear_input);
     }
@@ -58,6 +59,10 @@ impl ToString for Bind {
 #[derive(Resource, Default)]
 pub struct MousePosition(pub Vec2);
 
+// Systems that read the player input, anything simulating it should run after them
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputSet;
+
 // ·······
 // Systems
 // ·······
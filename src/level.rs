@@ -0,0 +1,437 @@
+#![allow(clippy::too_many_arguments)]
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+    reflect::TypePath,
+    tasks::futures_lite::AsyncReadExt,
+    utils::BoxedFuture,
+};
+use bevy_ecs_tilemap::prelude::*;
+use bevy_persistent::Persistent;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{CampaignProgress, GameScore},
+    editor::EditorTest,
+    game::GameMode,
+    tilemap::{
+        insert_start_end, play_to_real_size, Affinity, ForegroundTile, LevelSize, NeedsReset,
+        StartTile, Terrain, TilemapLayer, TilesAvailable,
+    },
+    GameState,
+};
+
+// ······
+// Plugin
+// ······
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .add_systems(
+                OnEnter(GameState::Play),
+                init_level.run_if(resource_exists::<NeedsReset>()),
+            )
+            .add_systems(
+                Update,
+                (spawn_level, check_level_goal).chain().run_if(
+                    in_state(GameState::Play)
+                        .and_then(resource_exists::<CurrentLevel>())
+                        .and_then(resource_exists::<TilesAvailable>()),
+                ),
+            )
+            .add_systems(OnEnter(GameState::End), clear_level)
+            .add_systems(Update, restart_run.run_if(in_state(GameState::End)))
+            .add_systems(OnExit(GameState::End), clear_level_result);
+    }
+}
+
+// ······
+// Assets
+// ······
+
+// Hand made level, loaded from a `.level.ron` file
+// Positions are relative to the bottom left corner of the level
+#[derive(Asset, TypePath, Serialize, Deserialize, Clone)]
+pub struct Level {
+    pub name: String,
+    pub size: (u32, u32),
+    pub tiles: u32,
+    #[serde(default)]
+    pub terrain: Vec<((u32, u32), Terrain)>,
+    pub starts: Vec<LevelSpawn>,
+    pub ends: Vec<LevelSpawn>,
+    pub goal: LevelGoal,
+    // Unused tiles needed for the second and third stars
+    #[serde(default)]
+    pub stars: [u32; 2],
+    #[serde(default)]
+    pub decorations: Vec<LevelDecoration>,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            name: "Custom".to_string(),
+            size: (8, 5),
+            tiles: 10,
+            terrain: Vec::new(),
+            starts: Vec::new(),
+            ends: Vec::new(),
+            goal: LevelGoal::Deliver(30),
+            stars: [2, 4],
+            decorations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelSpawn {
+    pub pos: (u32, u32),
+    // Score needed for this tile to appear
+    #[serde(default)]
+    pub score: u32,
+    // Seconds between spirits (only for starts)
+    #[serde(default)]
+    pub spawn_time: Option<f32>,
+    #[serde(default)]
+    pub affinity: Option<usize>,
+}
+
+// Purely visual foreground tile
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelDecoration {
+    pub pos: (u32, u32),
+    pub texture: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum LevelGoal {
+    Deliver(u32),
+    Survive(f32),
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Level, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes::<Level>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Level being played, if there is none the game is endless
+#[derive(Resource)]
+pub struct CurrentLevel(pub Handle<Level>);
+
+// Marks that the run is being ended to go right away to another state
+#[derive(Resource)]
+pub struct RestartRun(pub GameState);
+
+#[derive(Resource)]
+pub struct LevelResult {
+    pub name: String,
+    pub stars: u32,
+    pub tiles_left: u32,
+}
+
+#[derive(Resource, Default)]
+struct LevelProgress {
+    placed: bool,
+    starts: Vec<usize>,
+    ends: Vec<usize>,
+    time: f32,
+}
+
+// ·······
+// Systems
+// ·······
+
+fn init_level(mut cmd: Commands) {
+    cmd.insert_resource(LevelProgress::default());
+}
+
+fn spawn_level(
+    mut cmd: Commands,
+    current: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    score: Res<Persistent<GameScore>>,
+    level_size: Res<LevelSize>,
+    mut progress: ResMut<LevelProgress>,
+    tilemap: Query<(&TilemapLayer, &TileStorage)>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    let Some(level) = levels.get(&current.0) else {
+        return;
+    };
+    let (offset, _) = play_to_real_size(&level_size);
+    let to_tile = |(x, y): (u32, u32)| TilePos {
+        x: offset.x + x,
+        y: offset.y + y,
+    };
+
+    // Terrain and decorations are placed once, when the level starts
+    if !progress.placed {
+        for (layer, storage) in tilemap.iter() {
+            insert_level_tiles(&mut cmd, level, &level_size, layer, storage, &mut visible);
+        }
+        progress.placed = !tilemap.is_empty();
+    }
+
+    // Spawn the starts and ends that are scheduled for this score
+    for (i, start) in level.starts.iter().enumerate() {
+        if progress.starts.contains(&i) || score.score < start.score {
+            continue;
+        }
+        progress.starts.push(i);
+
+        for (layer, storage) in tilemap.iter() {
+            let mut tile = StartTile::default();
+            if let Some(time) = start.spawn_time {
+                tile.spawn_timer = Timer::from_seconds(time, TimerMode::Repeating);
+            }
+            insert_start_end(
+                &mut cmd,
+                &to_tile(start.pos),
+                Some(tile),
+                start.affinity.map(Affinity),
+                layer,
+                storage,
+                &mut visible,
+            );
+        }
+    }
+
+    for (i, end) in level.ends.iter().enumerate() {
+        if progress.ends.contains(&i) || score.score < end.score {
+            continue;
+        }
+        progress.ends.push(i);
+
+        for (layer, storage) in tilemap.iter() {
+            insert_start_end(
+                &mut cmd,
+                &to_tile(end.pos),
+                None,
+                end.affinity.map(Affinity),
+                layer,
+                storage,
+                &mut visible,
+            );
+        }
+    }
+}
+
+fn check_level_goal(
+    mut cmd: Commands,
+    time: Res<Time>,
+    current: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    mut score: ResMut<Persistent<GameScore>>,
+    available: Res<TilesAvailable>,
+    mut progress: ResMut<LevelProgress>,
+    mut campaign: ResMut<Persistent<CampaignProgress>>,
+    mut state: ResMut<NextState<GameState>>,
+    test: Option<Res<EditorTest>>,
+    mode: Res<GameMode>,
+) {
+    let Some(level) = levels.get(&current.0) else {
+        return;
+    };
+    progress.time += time.delta_seconds();
+
+    let completed = match level.goal {
+        LevelGoal::Deliver(count) => score.score >= count,
+        LevelGoal::Survive(seconds) => progress.time >= seconds,
+    };
+    if !completed {
+        return;
+    }
+
+    // One star for finishing, and one more for each tile threshold that was saved
+    let stars = 1 + level
+        .stars
+        .iter()
+        .filter(|tiles| available.0 >= **tiles)
+        .count() as u32;
+
+    // Puzzles keep their own record, and levels tested from the editor don't count for the campaign
+    if *mode == GameMode::Puzzle {
+        score
+            .update(|score| {
+                let best = score.best_puzzles.entry(level.name.clone()).or_default();
+                *best = available.0.max(*best);
+            })
+            .unwrap_or_else(|e| error!("Failed to save puzzle score: {}", e));
+    } else if test.is_none() {
+        campaign
+            .update(|campaign| {
+                let best = campaign.stars.entry(level.name.clone()).or_default();
+                *best = stars.max(*best);
+            })
+            .unwrap_or_else(|e| error!("Failed to save campaign progress: {}", e));
+    }
+
+    cmd.insert_resource(LevelResult {
+        name: level.name.clone(),
+        stars,
+        tiles_left: available.0,
+    });
+    state.set(GameState::End);
+}
+
+pub fn clear_level(mut cmd: Commands, restart: Option<Res<RestartRun>>) {
+    // If we are restarting, the level for the next run was already chosen
+    if restart.is_none() {
+        cmd.remove_resource::<CurrentLevel>();
+    }
+}
+
+fn restart_run(
+    mut cmd: Commands,
+    restart: Option<Res<RestartRun>>,
+    mut state: ResMut<NextState<GameState>>,
+) {
+    if let Some(restart) = restart {
+        cmd.remove_resource::<RestartRun>();
+        state.set(restart.0.clone());
+    }
+}
+
+fn clear_level_result(mut cmd: Commands) {
+    cmd.remove_resource::<LevelResult>();
+}
+
+// ·····
+// Extra
+// ·····
+
+// Add the terrain and decorations of a level to one of the tilemap layers
+pub fn insert_level_tiles(
+    cmd: &mut Commands,
+    level: &Level,
+    level_size: &LevelSize,
+    layer: &TilemapLayer,
+    storage: &TileStorage,
+    visible: &mut Query<&mut TileVisible>,
+) {
+    let (offset, _) = play_to_real_size(level_size);
+    let to_tile = |(x, y): (u32, u32)| TilePos {
+        x: offset.x + x,
+        y: offset.y + y,
+    };
+
+    match layer {
+        TilemapLayer::Background | TilemapLayer::RiverStix => {
+            for (pos, terrain) in level.terrain.iter() {
+                if let Some(entity) = storage.get(&to_tile(*pos)) {
+                    cmd.entity(entity).insert(*terrain);
+                }
+            }
+        }
+        TilemapLayer::Foreground => {
+            for decoration in level.decorations.iter() {
+                if let Some(entity) = storage.get(&to_tile(decoration.pos)) {
+                    cmd.entity(entity)
+                        .insert(ForegroundTile::Decoration(decoration.texture));
+                    if let Ok(mut visible) = visible.get_mut(entity) {
+                        visible.0 = true;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn custom_levels_dir() -> PathBuf {
+    Path::new(".data").join("levels")
+}
+
+// Levels saved from the editor, sorted by file name
+#[cfg(not(target_arch = "wasm32"))]
+pub fn custom_levels() -> Vec<(PathBuf, Level)> {
+    let Ok(dir) = std::fs::read_dir(custom_levels_dir()) else {
+        return Vec::new();
+    };
+
+    let mut levels = dir
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.to_string_lossy().ends_with(".level.ron"))
+        .filter_map(|path| {
+            let bytes = std::fs::read(&path).ok()?;
+            match ron::de::from_bytes::<Level>(&bytes) {
+                Ok(level) => Some((path, level)),
+                Err(e) => {
+                    warn!("Failed to read level {:?}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    levels.sort_by(|(a, _), (b, _)| a.cmp(b));
+    levels
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn custom_levels() -> Vec<(PathBuf, Level)> {
+    Vec::new()
+}
+
+// Save a level, if no path is given a new file is created
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_level(
+    level: &Level,
+    path: Option<&Path>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = custom_levels_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let name = level.name.to_lowercase().replace(' ', "_");
+            (0..)
+                .map(|i| dir.join(format!("{}_{}.level.ron", name, i)))
+                .find(|path| !path.exists())
+                .unwrap()
+        }
+    };
+
+    let data = ron::ser::to_string_pretty(level, ron::ser::PrettyConfig::default())?;
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_level(_: &Level, _: Option<&Path>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Err("Saving levels is not supported on the web".into())
+}
@@ -1,14 +1,28 @@
+mod animation;
+mod aseprite;
+mod attract;
 mod audio;
+mod bot;
 mod config;
+mod daily;
 mod debug;
+mod editor;
 mod end;
+mod erosion;
+mod ferry;
+mod fog;
 mod game;
+mod hint;
 mod hud;
 mod input;
+mod level;
 mod load;
 mod menu;
+mod modifiers;
+mod shop;
 mod spirits;
 mod tilemap;
+mod tileset;
 mod ui;
 
 use bevy::prelude::*;
@@ -23,6 +37,7 @@ pub enum GameState {
     Menu,
     Play,
     End,
+    Editor,
 }
 
 // Main game plugin
@@ -32,6 +47,7 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_state::<GameState>().add_plugins((
             load::LoadPlugin,
+            animation::SpriteAnimationPlugin,
             ui::UIPlugin,
             menu::MenuPlugin,
             hud::HudPlugin,
@@ -42,6 +58,19 @@ impl Plugin for GamePlugin {
             tilemap::TilePlugin,
             game::CharonPlugin,
             spirits::SpiritPlugin,
+            level::LevelPlugin,
+            editor::EditorPlugin,
+            (
+                bot::BotPlugin,
+                hint::HintPlugin,
+                attract::AttractPlugin,
+                daily::DailyPlugin,
+                modifiers::ModifierPlugin,
+                fog::FogPlugin,
+                erosion::ErosionPlugin,
+                shop::ShopPlugin,
+                ferry::FerryPlugin,
+            ),
         ));
 
         #[cfg(debug_assertions)]
@@ -1,7 +1,15 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::type_complexity)]
 
-use crate::{config::GameOptions, ui::*, GameState};
+use crate::{
+    animation::AnimationSet,
+    aseprite::{Aseprite, AsepriteLoader},
+    config::GameOptions,
+    level::Level,
+    tileset::Tileset,
+    ui::*,
+    GameState,
+};
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 use bevy_kira_audio::AudioSource;
@@ -21,11 +29,14 @@ pub struct LoadPlugin;
 
 impl Plugin for LoadPlugin {
     fn build(&self, app: &mut App) {
-        app.add_loading_state(LoadingState::new(GameState::Loading))
+        app.init_asset::<Aseprite>()
+            .init_asset_loader::<AsepriteLoader>()
+            .add_loading_state(LoadingState::new(GameState::Loading))
             .init_collection::<StartAssets>()
             .add_collection_to_loading_state::<_, GameAssets>(GameState::Loading)
             .add_collection_to_loading_state::<_, SpiritAssets>(GameState::Loading)
             .add_collection_to_loading_state::<_, TilemapAssets>(GameState::Loading)
+            .add_collection_to_loading_state::<_, LevelAssets>(GameState::Loading)
             .add_plugins((ProgressPlugin::new(GameState::Loading)
                 .continue_to(GameState::Menu)
                 .track_assets(),))
@@ -72,19 +83,46 @@ pub struct GameAssets {
 
 #[derive(AssetCollection, Resource)]
 pub struct SpiritAssets {
-    #[asset(texture_atlas(tile_size_x = 128., tile_size_y = 128., columns = 6, rows = 1))]
-    #[asset(path = "sprites/spirits_stix.png")]
-    pub stix: Handle<TextureAtlas>,
+    #[asset(path = "sprites/spirits_stix.aseprite")]
+    pub stix: Handle<Aseprite>,
 
-    #[asset(texture_atlas(tile_size_x = 128., tile_size_y = 128., columns = 4, rows = 1))]
-    #[asset(path = "sprites/spirits_phlege.png")]
-    pub phlege: Handle<TextureAtlas>,
+    #[asset(path = "animations/spirits_stix.anim.ron")]
+    pub stix_animations: Handle<AnimationSet>,
 }
 
+// Tilemap skins, the first one is the default
 #[derive(AssetCollection, Resource)]
 pub struct TilemapAssets {
-    #[asset(path = "sprites/river_stix.png")]
-    pub stix: Handle<Image>,
+    #[asset(
+        paths("tilesets/stix.tileset.ron", "tilesets/blocks.tileset.ron"),
+        collection(typed)
+    )]
+    pub tilesets: Vec<Handle<Tileset>>,
+}
+
+// Campaign levels, in the order they are played
+#[derive(AssetCollection, Resource)]
+pub struct LevelAssets {
+    #[asset(
+        paths(
+            "levels/01_first_steps.level.ron",
+            "levels/02_two_shores.level.ron",
+            "levels/03_the_rocks.level.ron",
+            "levels/04_colors.level.ron"
+        ),
+        collection(typed)
+    )]
+    pub levels: Vec<Handle<Level>>,
+    // Fixed layouts for the puzzle mode, one is picked for each run
+    #[asset(
+        paths(
+            "puzzles/01_narrow_pass.level.ron",
+            "puzzles/02_the_marsh.level.ron",
+            "puzzles/03_crossed_shores.level.ron"
+        ),
+        collection(typed)
+    )]
+    pub puzzles: Vec<Handle<Level>>,
 }
 
 // ··········
@@ -1,15 +1,29 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::type_complexity)]
 
+use std::path::PathBuf;
+
 use crate::{
-    config::{GameOptions, GameScore, Keybinds, Persistent, FONT_MULTIPLIERS, FONT_SIZES},
+    attract::Attract,
+    config::{
+        CampaignProgress, GameOptions, GameScore, Keybinds, Persistent, FONT_MULTIPLIERS,
+        FONT_SIZES, HINT_PENALTIES,
+    },
+    daily::{self, DailyText},
+    editor::{EditorLevel, EditorTest},
+    game::GameMode,
     input::Bind,
-    load::GameAssets,
+    level::{custom_levels, CurrentLevel, Level, RestartRun},
+    load::{GameAssets, LevelAssets, TilemapAssets},
+    modifiers::{score_multiplier, Modifier, MODIFIERS},
+    tilemap::NeedsReset,
+    tileset::Tileset,
     ui::*,
     GameState,
 };
 use bevy::prelude::*;
 use bevy::reflect::Struct;
+use rand::Rng;
 
 // ······
 // Plugin
@@ -20,12 +34,14 @@ pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_state::<MenuState>()
+            .init_resource::<CustomLevels>()
             .add_systems(OnEnter(GameState::Menu), init_menu)
             .add_systems(
                 Update,
                 (
                     handle_buttons.run_if(in_state(GameState::Menu)),
-                    return_to_menu,
+                    // The demo run handles its own input
+                    return_to_menu.run_if(not(resource_exists::<Attract>())),
                 ),
             )
             .add_systems(OnExit(GameState::Menu), exit_menu)
@@ -51,6 +67,10 @@ impl Plugin for MenuPlugin {
 pub enum MenuState {
     #[default]
     Main,
+    Modifiers,
+    Modes,
+    Campaign,
+    Custom,
     Settings,
     Keybinds,
     Rebinding,
@@ -68,6 +88,11 @@ struct MenuStarting;
 #[derive(Resource)]
 struct KeyBeingRebound(String);
 
+// Levels saved from the editor, loaded when the custom screen is built
+// Each file keeps its handle, so playing it again doesn't add a new asset
+#[derive(Resource, Default)]
+struct CustomLevels(Vec<(PathBuf, Handle<Level>)>);
+
 // ··········
 // Components
 // ··········
@@ -75,16 +100,32 @@ struct KeyBeingRebound(String);
 #[derive(Component)]
 struct MenuText;
 
+// Image behind the main menu, hidden while the demo run plays there
+#[derive(Component)]
+struct MenuBackground;
+
 #[derive(Component)]
 pub enum MenuButton {
     Play,
+    PlayLevel(Handle<Level>),
+    PlayMode(GameMode),
     GoMain,
+    GoModes,
+    GoCampaign,
+    GoCustom,
     GoSettings,
+    NewLevel,
+    EditLevel(usize),
     GoKeybinds,
     GoVisual,
     RemapKeybind(String),
     ResetKeybinds,
     ChangeFont(String),
+    ToggleColorMatch,
+    ToggleHexGrid,
+    ChangeTileset,
+    ChangeHintPenalty,
+    ToggleModifier(Modifier),
 }
 
 // ·······
@@ -101,7 +142,7 @@ fn init_menu(
     // Main menu layout
     if let Ok(node) = node.get_single_mut() {
         cmd.insert_resource(MenuStarting);
-        layout_main(cmd, node, &style, score.best_score, &assets.start_screen);
+        layout_main(cmd, node, &style, &score, &assets.start_screen);
     }
 }
 
@@ -116,6 +157,10 @@ fn handle_buttons(
     >,
     mut opts: ResMut<Persistent<GameOptions>>,
     mut keybinds: ResMut<Persistent<Keybinds>>,
+    level_assets: Res<LevelAssets>,
+    needs_reset: Option<Res<NeedsReset>>,
+    tile_assets: Res<TilemapAssets>,
+    tilesets: Res<Assets<Tileset>>,
 ) {
     for (inter, button, child, mut bg) in &mut buttons {
         let child = child.iter().next();
@@ -127,11 +172,62 @@ fn handle_buttons(
 
                     match button {
                         MenuButton::Play => {
-                            game_state.set(GameState::Play);
+                            // Continue a paused run, or choose the modifiers for a new one
+                            if needs_reset.is_some() {
+                                menu_state.set(MenuState::Modifiers);
+                            } else {
+                                game_state.set(GameState::Play);
+                            }
+                        }
+                        MenuButton::PlayMode(mode) => {
+                            if *mode == GameMode::Puzzle {
+                                let puzzles = &level_assets.puzzles;
+                                if puzzles.is_empty() {
+                                    continue;
+                                }
+                                let i = rand::thread_rng().gen_range(0..puzzles.len());
+                                cmd.insert_resource(CurrentLevel(puzzles[i].clone()));
+                            } else {
+                                cmd.remove_resource::<CurrentLevel>();
+                            }
+                            cmd.insert_resource(*mode);
+                            start_run(&mut cmd, &mut game_state, &needs_reset, GameState::Play);
+                        }
+                        MenuButton::PlayLevel(level) => {
+                            cmd.insert_resource(GameMode::Endless);
+                            cmd.insert_resource(CurrentLevel(level.clone()));
+                            start_run(&mut cmd, &mut game_state, &needs_reset, GameState::Play);
+                        }
+                        MenuButton::NewLevel => {
+                            cmd.insert_resource(EditorLevel::default());
+                            start_run(&mut cmd, &mut game_state, &needs_reset, GameState::Editor);
+                        }
+                        MenuButton::EditLevel(i) => {
+                            if let Some((path, level)) = custom_levels().into_iter().nth(*i) {
+                                cmd.insert_resource(EditorLevel {
+                                    level,
+                                    path: Some(path),
+                                });
+                                start_run(
+                                    &mut cmd,
+                                    &mut game_state,
+                                    &needs_reset,
+                                    GameState::Editor,
+                                );
+                            }
                         }
                         MenuButton::GoMain => {
                             menu_state.set(MenuState::Main);
                         }
+                        MenuButton::GoModes => {
+                            menu_state.set(MenuState::Modes);
+                        }
+                        MenuButton::GoCampaign => {
+                            menu_state.set(MenuState::Campaign);
+                        }
+                        MenuButton::GoCustom => {
+                            menu_state.set(MenuState::Custom);
+                        }
                         MenuButton::GoSettings => {
                             menu_state.set(MenuState::Settings);
                         }
@@ -175,6 +271,52 @@ fn handle_buttons(
                             })
                             .unwrap_or_else(|e| error!("Failed to change font size: {}", e));
                         }
+                        MenuButton::ToggleColorMatch => {
+                            opts.update(|opts| {
+                                opts.color_match = !opts.color_match;
+                            })
+                            .unwrap_or_else(|e| error!("Failed to toggle color match: {}", e));
+                        }
+                        MenuButton::ToggleHexGrid => {
+                            opts.update(|opts| {
+                                opts.hex_grid = !opts.hex_grid;
+                            })
+                            .unwrap_or_else(|e| error!("Failed to toggle hex grid: {}", e));
+                        }
+                        MenuButton::ChangeHintPenalty => {
+                            let i = HINT_PENALTIES
+                                .iter()
+                                .position(|penalty| *penalty == opts.hint_penalty);
+                            let next =
+                                HINT_PENALTIES[i.map_or(0, |i| i + 1) % HINT_PENALTIES.len()];
+                            opts.update(|opts| {
+                                opts.hint_penalty = next;
+                            })
+                            .unwrap_or_else(|e| error!("Failed to change hint penalty: {}", e));
+                        }
+                        MenuButton::ToggleModifier(modifier) => {
+                            opts.update(|opts| {
+                                if let Some(i) = opts.modifiers.iter().position(|m| m == modifier) {
+                                    opts.modifiers.remove(i);
+                                } else {
+                                    opts.modifiers.push(*modifier);
+                                }
+                            })
+                            .unwrap_or_else(|e| error!("Failed to toggle modifier: {}", e));
+                        }
+                        MenuButton::ChangeTileset => {
+                            let names = tileset_names(&tile_assets, &tilesets);
+                            if names.is_empty() {
+                                continue;
+                            }
+                            // The default tileset has an empty name, so it counts as the first
+                            let i = names.iter().position(|name| *name == opts.tileset);
+                            let next = names[(i.unwrap_or(0) + 1) % names.len()].clone();
+                            opts.update(|opts| {
+                                opts.tileset = next;
+                            })
+                            .unwrap_or_else(|e| error!("Failed to change tileset: {}", e));
+                        }
                     }
                 }
                 Interaction::Hovered => {
@@ -207,6 +349,12 @@ fn clean_menu(
     rebind_key: Option<Res<KeyBeingRebound>>,
     menu_starting: Option<Res<MenuStarting>>,
     score: Res<Persistent<GameScore>>,
+    level_assets: Res<LevelAssets>,
+    mut levels: ResMut<Assets<Level>>,
+    mut custom: ResMut<CustomLevels>,
+    campaign: Res<Persistent<CampaignProgress>>,
+    tile_assets: Res<TilemapAssets>,
+    tilesets: Res<Assets<Tileset>>,
 ) {
     if menu_starting.is_some() {
         return;
@@ -217,10 +365,49 @@ fn clean_menu(
             entity.despawn_descendants();
 
             match state.get() {
-                MenuState::Main => {
-                    layout_main(cmd, node, &style, score.best_score, &assets.start_screen)
+                MenuState::Main => layout_main(cmd, node, &style, &score, &assets.start_screen),
+                MenuState::Modifiers => layout_modifiers(cmd, node, &style, &opts, &score),
+                MenuState::Modes => layout_modes(cmd, node, &style, &score),
+                MenuState::Campaign => {
+                    let levels = level_assets
+                        .levels
+                        .iter()
+                        .filter_map(|handle| levels.get(handle).map(|level| (handle, level)))
+                        .map(|(handle, level)| {
+                            let stars = campaign.stars.get(&level.name).copied().unwrap_or(0);
+                            (handle.clone(), level.name.clone(), stars)
+                        })
+                        .collect::<Vec<_>>();
+                    layout_campaign(cmd, node, &style, &levels)
+                }
+                MenuState::Custom => {
+                    // Files that were already loaded keep their handle and get the saved changes
+                    let loaded = custom_levels()
+                        .into_iter()
+                        .map(|(path, level)| {
+                            let name = level.name.clone();
+                            let handle = match custom.0.iter().find(|(p, _)| *p == path) {
+                                Some((_, handle)) => {
+                                    levels.insert(handle, level);
+                                    handle.clone()
+                                }
+                                None => levels.add(level),
+                            };
+                            (path, handle, name)
+                        })
+                        .collect::<Vec<_>>();
+                    custom.0 = loaded
+                        .iter()
+                        .map(|(path, handle, _)| (path.clone(), handle.clone()))
+                        .collect();
+
+                    let levels = loaded
+                        .into_iter()
+                        .map(|(_, handle, name)| (handle, name))
+                        .collect::<Vec<_>>();
+                    layout_custom(cmd, node, &style, &levels)
                 }
-                MenuState::Settings => layout_options(cmd, node, &style),
+                MenuState::Settings => layout_options(cmd, node, &style, &opts),
                 MenuState::Keybinds => layout_keybinds(cmd, node, &style, &keybinds),
                 MenuState::Rebinding => {
                     let rebind_key = match rebind_key {
@@ -229,7 +416,16 @@ fn clean_menu(
                     };
                     layout_rebinding(cmd, node, &style, &rebind_key)
                 }
-                MenuState::Visual => layout_visual(cmd, node, &style, &opts),
+                MenuState::Visual => {
+                    let names = tileset_names(&tile_assets, &tilesets);
+                    let tileset = names
+                        .iter()
+                        .find(|name| **name == opts.tileset)
+                        .or(names.first())
+                        .cloned()
+                        .unwrap_or_default();
+                    layout_visual(cmd, node, &style, &opts, &tileset)
+                }
                 MenuState::Exit => {}
             }
         }
@@ -240,25 +436,46 @@ fn exit_menu(
     mut cmd: Commands,
     mut next_state: ResMut<NextState<MenuState>>,
     mut node: Query<(Entity, &mut BackgroundColor), With<UiNode>>,
+    background: Query<Entity, With<MenuBackground>>,
+    attract: Option<Res<Attract>>,
 ) {
     if let Ok((node, mut bg)) = node.get_single_mut() {
+        *bg = Color::rgba(0., 0., 0., 0.).into();
+
+        // The demo run plays behind the main menu, so only its background is removed
+        if attract.is_some() {
+            for entity in background.iter() {
+                cmd.entity(entity).despawn_recursive();
+            }
+            return;
+        }
         if let Some(mut entity) = cmd.get_entity(node) {
             entity.despawn_descendants();
         }
-        *bg = Color::rgba(0., 0., 0., 0.).into();
     }
 
     next_state.set(MenuState::Exit);
 }
 
 fn return_to_menu(
+    mut cmd: Commands,
+    current_game_state: Res<State<GameState>>,
     mut game_state: ResMut<NextState<GameState>>,
     current_menu_state: Res<State<MenuState>>,
     mut next_menu_state: ResMut<NextState<MenuState>>,
     input: Res<Input<Bind>>,
     keybinds: Res<Persistent<Keybinds>>,
+    test: Option<Res<EditorTest>>,
 ) {
     if keybinds.pause.iter().any(|bind| input.just_pressed(*bind)) {
+        // When testing a level, go back to the editor instead
+        if test.is_some() && *current_game_state.get() == GameState::Play {
+            cmd.insert_resource(RestartRun(GameState::Editor));
+            game_state.set(GameState::End);
+            return;
+        }
+        cmd.remove_resource::<EditorTest>();
+
         match *current_menu_state.get() {
             MenuState::Keybinds | MenuState::Visual => next_menu_state.set(MenuState::Settings),
             _ => next_menu_state.set(MenuState::Main),
@@ -321,11 +538,35 @@ fn remap_keybind(
 // Extra
 // ·····
 
+// If there is a run in progress, end it before going to the next state
+fn start_run(
+    cmd: &mut Commands,
+    game_state: &mut NextState<GameState>,
+    needs_reset: &Option<Res<NeedsReset>>,
+    state: GameState,
+) {
+    if needs_reset.is_none() {
+        cmd.insert_resource(RestartRun(state));
+        game_state.set(GameState::End);
+    } else {
+        game_state.set(state);
+    }
+}
+
+fn tileset_names(tile_assets: &TilemapAssets, tilesets: &Assets<Tileset>) -> Vec<String> {
+    tile_assets
+        .tilesets
+        .iter()
+        .filter_map(|handle| tilesets.get(handle))
+        .map(|tileset| tileset.name.clone())
+        .collect()
+}
+
 fn layout_main(
     mut cmd: Commands,
     node: Entity,
     style: &UIStyle,
-    best_score: u32,
+    score: &GameScore,
     background: &Handle<Image>,
 ) {
     if let Some(mut node) = cmd.get_entity(node) {
@@ -346,15 +587,23 @@ fn layout_main(
                     ..default()
                 },
                 UI_LAYER,
+                MenuBackground,
             ));
 
             UIText::simple(style, "Entities' repose")
                 .with_title()
                 .add(parent);
-            if best_score > 0 {
-                UIText::simple(style, &format!("Most saved: {}", best_score)).add(parent);
+            if score.best_score > 0 {
+                UIText::simple(style, &format!("Most saved: {}", score.best_score)).add(parent);
             }
 
+            // The daily challenge can only be played once
+            let played = score.last_daily == daily::today();
+            if played {
+                UIText::simple(style, &format!("Today's daily: {}", score.daily_score)).add(parent);
+            }
+            UIText::new(style, &daily::countdown_text(), Some(DailyText)).add(parent);
+
             parent
                 .spawn((
                     NodeBundle {
@@ -374,14 +623,183 @@ fn layout_main(
                     UI_LAYER,
                 ))
                 .with_children(|parent| {
-                    UIButton::new(style, "Play", Some(MenuButton::Play)).add(parent);
-                    UIButton::new(style, "Settings", Some(MenuButton::GoSettings)).add(parent);
+                    let buttons = [
+                        ("Play", Some(MenuButton::Play)),
+                        ("Modes", Some(MenuButton::GoModes)),
+                        (
+                            if played { "Played" } else { "Daily" },
+                            (!played).then_some(MenuButton::PlayMode(GameMode::Daily)),
+                        ),
+                        ("Campaign", Some(MenuButton::GoCampaign)),
+                        ("Editor", Some(MenuButton::GoCustom)),
+                        ("Settings", Some(MenuButton::GoSettings)),
+                    ];
+                    for (text, button) in buttons {
+                        UIButton::new(style, text, button)
+                            .with_width(Val::Px(160.))
+                            .add(parent);
+                    }
+                });
+        });
+    }
+}
+
+fn layout_modifiers(
+    mut cmd: Commands,
+    node: Entity,
+    style: &UIStyle,
+    opts: &GameOptions,
+    score: &GameScore,
+) {
+    if let Some(mut node) = cmd.get_entity(node) {
+        node.with_children(|parent| {
+            UIText::simple(style, "Modifiers").with_title().add(parent);
+
+            for modifier in MODIFIERS {
+                UIOption::new(style, modifier.name()).add(parent, |row| {
+                    UIButton::new(
+                        style,
+                        if opts.modifiers.contains(&modifier) {
+                            "On"
+                        } else {
+                            "Off"
+                        },
+                        Some(MenuButton::ToggleModifier(modifier)),
+                    )
+                    .with_width(Val::Px(40.))
+                    .add(row);
+                });
+            }
+            let mult = score_multiplier(&opts.modifiers);
+            UIText::simple(style, &format!("Score x{}.{:02}", mult / 100, mult % 100)).add(parent);
+
+            // Last runs, with the modifiers they were played with
+            for record in score.history.iter().rev().take(3) {
+                let modifiers = record
+                    .modifiers
+                    .iter()
+                    .map(|modifier| snake_to_upper(modifier.name()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let text = if modifiers.is_empty() {
+                    format!("{} {}", record.mode, record.score)
+                } else {
+                    format!("{} {} ({})", record.mode, record.score, modifiers)
+                };
+                UIText::simple(style, &text).add(parent);
+            }
+
+            UIButton::new(
+                style,
+                "Start",
+                Some(MenuButton::PlayMode(GameMode::Endless)),
+            )
+            .add(parent);
+            UIButton::new(style, "Back", Some(MenuButton::GoMain)).add(parent);
+        });
+    }
+}
+
+fn layout_modes(mut cmd: Commands, node: Entity, style: &UIStyle, score: &GameScore) {
+    if let Some(mut node) = cmd.get_entity(node) {
+        node.with_children(|parent| {
+            UIText::simple(style, "Modes").with_title().add(parent);
+
+            // Puzzles have a record each, so show how many were solved
+            let modes = [
+                (GameMode::Endless, format!("Best {}", score.best_score)),
+                (GameMode::Zen, format!("Best {}", score.best_zen)),
+                (
+                    GameMode::TimeAttack,
+                    format!("Best {}", score.best_time_attack),
+                ),
+                (
+                    GameMode::Puzzle,
+                    format!("Solved {}", score.best_puzzles.len()),
+                ),
+            ];
+            for (mode, label) in modes {
+                UIOption::new(style, mode.name()).add(parent, |row| {
+                    UIButton::new(style, &label, Some(MenuButton::PlayMode(mode)))
+                        .with_width(Val::Px(100.))
+                        .add(row);
+                });
+            }
+
+            UIButton::new(style, "Back", Some(MenuButton::GoMain)).add(parent);
+        });
+    }
+}
+
+fn layout_campaign(
+    mut cmd: Commands,
+    node: Entity,
+    style: &UIStyle,
+    levels: &[(Handle<Level>, String, u32)],
+) {
+    if let Some(mut node) = cmd.get_entity(node) {
+        node.with_children(|parent| {
+            UIText::simple(style, "Campaign").with_title().add(parent);
+
+            // Each level is unlocked after completing the previous one
+            for (i, (handle, name, stars)) in levels.iter().enumerate() {
+                let unlocked = i == 0 || levels[i - 1].2 > 0;
+                UIOption::new(style, name).add(parent, |row| {
+                    if unlocked {
+                        UIButton::new(
+                            style,
+                            &format!(
+                                "{}{}",
+                                "*".repeat(*stars as usize),
+                                "-".repeat(3 - (*stars as usize).min(3))
+                            ),
+                            Some(MenuButton::PlayLevel(handle.clone())),
+                        )
+                        .with_width(Val::Px(80.))
+                        .add(row);
+                    } else {
+                        UIButton::<MenuButton>::new(style, "Locked", None)
+                            .with_width(Val::Px(80.))
+                            .add(row);
+                    }
                 });
+            }
+
+            UIButton::new(style, "Back", Some(MenuButton::GoMain)).add(parent);
         });
     }
 }
 
-fn layout_options(mut cmd: Commands, node: Entity, style: &UIStyle) {
+fn layout_custom(
+    mut cmd: Commands,
+    node: Entity,
+    style: &UIStyle,
+    levels: &[(Handle<Level>, String)],
+) {
+    if let Some(mut node) = cmd.get_entity(node) {
+        node.with_children(|parent| {
+            UIText::simple(style, "Custom levels")
+                .with_title()
+                .add(parent);
+
+            for (i, (handle, name)) in levels.iter().enumerate() {
+                UIOption::new(style, name).add(parent, |row| {
+                    UIButton::new(style, "Play", Some(MenuButton::PlayLevel(handle.clone())))
+                        .with_width(Val::Px(60.))
+                        .add(row);
+                    UIButton::new(style, "Edit", Some(MenuButton::EditLevel(i)))
+                        .with_width(Val::Px(60.))
+                        .add(row);
+                });
+            }
+
+            UIButton::new(style, "New level", Some(MenuButton::NewLevel)).add(parent);
+            UIButton::new(style, "Back", Some(MenuButton::GoMain)).add(parent);
+        });
+    }
+}
+
+fn layout_options(mut cmd: Commands, node: Entity, style: &UIStyle, opts: &GameOptions) {
     if let Some(mut node) = cmd.get_entity(node) {
         node.with_children(|parent| {
             UIText::simple(style, "Settings").with_title().add(parent);
@@ -389,6 +807,36 @@ fn layout_options(mut cmd: Commands, node: Entity, style: &UIStyle) {
             UIButton::new(style, "Keybinds", Some(MenuButton::GoKeybinds)).add(parent);
             UIButton::new(style, "Visual", Some(MenuButton::GoVisual)).add(parent);
 
+            UIOption::new(style, "color_match").add(parent, |row| {
+                UIButton::new(
+                    style,
+                    if opts.color_match { "On" } else { "Off" },
+                    Some(MenuButton::ToggleColorMatch),
+                )
+                .with_width(Val::Px(40.))
+                .add(row);
+            });
+
+            UIOption::new(style, "hex_grid").add(parent, |row| {
+                UIButton::new(
+                    style,
+                    if opts.hex_grid { "On" } else { "Off" },
+                    Some(MenuButton::ToggleHexGrid),
+                )
+                .with_width(Val::Px(40.))
+                .add(row);
+            });
+
+            UIOption::new(style, "hint_penalty").add(parent, |row| {
+                UIButton::new(
+                    style,
+                    &opts.hint_penalty.to_string(),
+                    Some(MenuButton::ChangeHintPenalty),
+                )
+                .with_width(Val::Px(40.))
+                .add(row);
+            });
+
             UIButton::new(style, "Back", Some(MenuButton::GoMain)).add(parent);
         });
     }
@@ -442,7 +890,13 @@ fn layout_rebinding(mut cmd: Commands, node: Entity, style: &UIStyle, key: &str)
     }
 }
 
-fn layout_visual(mut cmd: Commands, node: Entity, style: &UIStyle, opts: &GameOptions) {
+fn layout_visual(
+    mut cmd: Commands,
+    node: Entity,
+    style: &UIStyle,
+    opts: &GameOptions,
+    tileset: &str,
+) {
     if let Some(mut node) = cmd.get_entity(node) {
         node.with_children(|parent| {
             UIText::simple(style, "Visual settings")
@@ -464,6 +918,12 @@ fn layout_visual(mut cmd: Commands, node: Entity, style: &UIStyle, opts: &GameOp
                 }
             }
 
+            UIOption::new(style, "tileset").add(parent, |row| {
+                UIButton::new(style, tileset, Some(MenuButton::ChangeTileset))
+                    .with_width(Val::Px(100.))
+                    .add(row);
+            });
+
             UIButton::new(style, "Back", Some(MenuButton::GoSettings)).add(parent);
         });
     }
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    tilemap::{LevelSize, TilesAvailable},
+    GameState,
+};
+
+// ······
+// Plugin
+// ······
+
+pub struct ModifierPlugin;
+
+impl Plugin for ModifierPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RunModifiers>().add_systems(
+            Update,
+            halve_tiles.run_if(in_state(GameState::Play).and_then(resource_added::<LevelSize>())),
+        );
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Optional rules that make a run harder in exchange for more score
+#[derive(Serialize, Deserialize, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Modifier {
+    // Starts and ends need half the score to appear
+    DoubleSpawns,
+    // Half of the initial tiles and of the tiles given with each start and end
+    HalfTiles,
+    // The level keeps its initial size
+    NoGrowth,
+    // Starts and ends come in pairs, one on each side of the level
+    MirroredSpawns,
+    // Only the tiles near rivers and spirits are visible
+    Fog,
+    // Unused rivers silt up and busy ones get deeper
+    Decay,
+}
+
+pub const MODIFIERS: [Modifier; 6] = [
+    Modifier::DoubleSpawns,
+    Modifier::HalfTiles,
+    Modifier::NoGrowth,
+    Modifier::MirroredSpawns,
+    Modifier::Fog,
+    Modifier::Decay,
+];
+
+impl Modifier {
+    pub fn name(&self) -> &str {
+        match self {
+            Modifier::DoubleSpawns => "double_spawns",
+            Modifier::HalfTiles => "half_tiles",
+            Modifier::NoGrowth => "no_growth",
+            Modifier::MirroredSpawns => "mirrored_spawns",
+            Modifier::Fog => "fog_of_war",
+            Modifier::Decay => "decaying_rivers",
+        }
+    }
+
+    // Extra percentage of score earned while it is active
+    fn score_bonus(&self) -> u32 {
+        match self {
+            Modifier::DoubleSpawns => 50,
+            Modifier::HalfTiles => 50,
+            Modifier::NoGrowth => 25,
+            Modifier::MirroredSpawns => 25,
+            Modifier::Fog => 50,
+            Modifier::Decay => 25,
+        }
+    }
+}
+
+// Modifiers of the current run, copied from the options when it starts
+#[derive(Resource, Default)]
+pub struct RunModifiers {
+    pub list: Vec<Modifier>,
+    // Fractions of a point that haven't been awarded yet
+    carry: u32,
+}
+
+impl RunModifiers {
+    pub fn new(list: Vec<Modifier>) -> Self {
+        Self { list, carry: 0 }
+    }
+
+    pub fn has(&self, modifier: Modifier) -> bool {
+        self.list.contains(&modifier)
+    }
+
+    // Apply the score multiplier to the points of a delivery
+    pub fn award(&mut self, points: u32) -> u32 {
+        let total = points * score_multiplier(&self.list) + self.carry;
+        self.carry = total % 100;
+        total / 100
+    }
+}
+
+// ·······
+// Systems
+// ·······
+
+fn halve_tiles(modifiers: Res<RunModifiers>, mut available: ResMut<TilesAvailable>) {
+    if modifiers.has(Modifier::HalfTiles) {
+        available.0 /= 2;
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Percentage of the score earned, the bonuses of each modifier stack on top of each other
+pub fn score_multiplier(list: &[Modifier]) -> u32 {
+    list.iter().fold(100, |mult, modifier| {
+        mult * (100 + modifier.score_bonus()) / 100
+    })
+}
@@ -0,0 +1,378 @@
+#![allow(clippy::too_many_arguments)]
+
+use bevy::prelude::*;
+use bevy_persistent::Persistent;
+
+use crate::{
+    attract::Attract,
+    bot::Bot,
+    config::{GameOptions, GameScore},
+    game::start_score,
+    level::CurrentLevel,
+    spirits::EndTimer,
+    tilemap::{NeedsReset, StartTile, TilesAvailable},
+    ui::*,
+    GameState,
+};
+
+// First milestone that opens the shop, and how many milestones until the next one
+const SHOP_FIRST: usize = 3;
+const SHOP_EVERY: usize = 2;
+
+// Tiles bought at once
+const SHOP_TILES: u32 = 5;
+// Levels of the upgrades and what each one does
+const MAX_UPGRADE: u32 = 3;
+const THROUGHPUT_STEP: f32 = 0.75;
+const SPEED_STEP: f32 = 0.15;
+// Starts spawn spirits this much slower while the slowdown lasts
+const SLOWDOWN: f32 = 0.5;
+const SLOWDOWN_SECONDS: f32 = 30.;
+// Ferries that can be bought in a run
+const MAX_FERRIES: u32 = 3;
+
+// ······
+// Plugin
+// ······
+
+// Obols are earned with each delivery and spent in the shop between waves
+pub struct ShopPlugin;
+
+impl Plugin for ShopPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Shop::default())
+            .add_systems(
+                OnEnter(GameState::Play),
+                init_shop.run_if(resource_exists::<NeedsReset>()),
+            )
+            .add_systems(
+                Update,
+                (
+                    // The bot never clicks on the shop, so it doesn't open for it
+                    open_shop.run_if(
+                        resource_changed::<Persistent<GameScore>>()
+                            .and_then(not(resource_exists::<ShopOpen>()))
+                            .and_then(not(resource_exists::<CurrentLevel>()))
+                            .and_then(not(resource_exists::<Attract>()))
+                            .and_then(not(bot_active)),
+                    ),
+                    handle_buttons.run_if(resource_exists::<ShopOpen>()),
+                    close_shop.run_if(resource_exists::<ShopOpen>().and_then(bot_active)),
+                    tick_slowdown,
+                )
+                    .run_if(in_state(GameState::Play)),
+            )
+            .add_systems(OnExit(GameState::Play), close_shop);
+    }
+}
+
+// ·········
+// Resources
+// ·········
+
+// Currency and upgrades of the current run
+#[derive(Resource, Default)]
+pub struct Shop {
+    pub obols: u32,
+    // Ferries bought that haven't been placed on a start
+    pub ferries: u32,
+    pub ferry_level: u32,
+    ferries_bought: u32,
+    throughput: u32,
+    speed: u32,
+    slowdown: Option<Timer>,
+    // The lose counters can only be reset once per run
+    reset_used: bool,
+    // Index of the start milestone that opens the shop next
+    milestone: usize,
+}
+
+impl Shop {
+    // Multiplier for the speed of the spirits
+    pub fn speed(&self) -> f32 {
+        1. + self.speed as f32 * SPEED_STEP
+    }
+
+    // Multiplier for how fast the starts spawn spirits
+    pub fn spawn_rate(&self) -> f32 {
+        if self.slowdown.is_some() {
+            SLOWDOWN
+        } else {
+            1.
+        }
+    }
+}
+
+// The game is paused while the shop is open
+#[derive(Resource)]
+pub struct ShopOpen;
+
+// ··········
+// Components
+// ··········
+
+#[derive(Component)]
+struct ShopPanel;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ShopItem {
+    Tiles,
+    Throughput,
+    Speed,
+    Slowdown,
+    ResetLoseCounter,
+    Ferry,
+    FerryUpgrade,
+}
+
+const SHOP_ITEMS: [ShopItem; 7] = [
+    ShopItem::Tiles,
+    ShopItem::Throughput,
+    ShopItem::Speed,
+    ShopItem::Slowdown,
+    ShopItem::ResetLoseCounter,
+    ShopItem::Ferry,
+    ShopItem::FerryUpgrade,
+];
+
+impl ShopItem {
+    fn name(&self) -> &str {
+        match self {
+            ShopItem::Tiles => "extra_tiles",
+            ShopItem::Throughput => "faster_ends",
+            ShopItem::Speed => "faster_rivers",
+            ShopItem::Slowdown => "slow_spawns",
+            ShopItem::ResetLoseCounter => "calm_the_starts",
+            ShopItem::Ferry => "new_ferry",
+            ShopItem::FerryUpgrade => "bigger_ferries",
+        }
+    }
+
+    // Upgrades get more expensive with each level, None if it can't be bought anymore
+    fn cost(&self, shop: &Shop) -> Option<u32> {
+        match self {
+            ShopItem::Tiles => Some(3),
+            ShopItem::Throughput => {
+                (shop.throughput < MAX_UPGRADE).then_some(5 * (shop.throughput + 1))
+            }
+            ShopItem::Speed => (shop.speed < MAX_UPGRADE).then_some(5 * (shop.speed + 1)),
+            ShopItem::Slowdown => shop.slowdown.is_none().then_some(4),
+            ShopItem::ResetLoseCounter => (!shop.reset_used).then_some(8),
+            ShopItem::Ferry => {
+                (shop.ferries_bought < MAX_FERRIES).then_some(10 * (shop.ferries_bought + 1))
+            }
+            ShopItem::FerryUpgrade => {
+                (shop.ferry_level < MAX_UPGRADE).then_some(6 * (shop.ferry_level + 1))
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+enum ShopButton {
+    Buy(ShopItem),
+    Close,
+}
+
+// ·······
+// Systems
+// ·······
+
+fn bot_active(bot: Res<Bot>) -> bool {
+    bot.active
+}
+
+fn init_shop(mut shop: ResMut<Shop>, mut end_timer: ResMut<EndTimer>) {
+    *shop = Shop {
+        milestone: SHOP_FIRST,
+        ..default()
+    };
+    *end_timer = EndTimer::default();
+}
+
+// Open the shop each time the score goes past one of the milestones
+fn open_shop(
+    mut cmd: Commands,
+    mut shop: ResMut<Shop>,
+    score: Res<Persistent<GameScore>>,
+    style: Res<UIStyle>,
+    mut time: ResMut<Time<Virtual>>,
+    node: Query<Entity, With<UiNode>>,
+) {
+    if score.delivered < start_score(shop.milestone) {
+        return;
+    }
+    shop.milestone += SHOP_EVERY;
+
+    time.pause();
+    cmd.insert_resource(ShopOpen);
+    if let Ok(node) = node.get_single() {
+        layout_shop(&mut cmd, node, &style, &shop);
+    }
+}
+
+fn handle_buttons(
+    mut cmd: Commands,
+    mut shop: ResMut<Shop>,
+    mut available: ResMut<TilesAvailable>,
+    mut end_timer: ResMut<EndTimer>,
+    mut time: ResMut<Time<Virtual>>,
+    mut starts: Query<&mut StartTile>,
+    mut text: Query<&mut Text>,
+    mut buttons: Query<
+        (&Interaction, &ShopButton, &Children, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    panel: Query<Entity, With<ShopPanel>>,
+    node: Query<Entity, With<UiNode>>,
+    opts: Res<Persistent<GameOptions>>,
+    style: Res<UIStyle>,
+) {
+    for (inter, button, child, mut bg) in &mut buttons {
+        let child = child.iter().next();
+        let Some(mut text) = child.and_then(|child| text.get_mut(*child).ok()) else {
+            continue;
+        };
+        match inter {
+            Interaction::Pressed => {
+                bg.0 = opts.color.dark;
+                text.sections[0].style.color = opts.color.light;
+
+                let item = match button {
+                    ShopButton::Buy(item) => *item,
+                    ShopButton::Close => {
+                        for entity in panel.iter() {
+                            cmd.entity(entity).despawn_recursive();
+                        }
+                        cmd.remove_resource::<ShopOpen>();
+                        time.unpause();
+                        continue;
+                    }
+                };
+                let Some(cost) = item.cost(&shop).filter(|cost| *cost <= shop.obols) else {
+                    continue;
+                };
+                shop.obols -= cost;
+
+                match item {
+                    ShopItem::Tiles => available.0 += SHOP_TILES,
+                    ShopItem::Throughput => {
+                        shop.throughput += 1;
+                        let duration = end_timer.0.duration().mul_f32(THROUGHPUT_STEP);
+                        end_timer.0.set_duration(duration);
+                    }
+                    ShopItem::Speed => shop.speed += 1,
+                    ShopItem::Slowdown => {
+                        let timer = Timer::from_seconds(SLOWDOWN_SECONDS, TimerMode::Once);
+                        shop.slowdown = Some(timer);
+                    }
+                    ShopItem::ResetLoseCounter => {
+                        shop.reset_used = true;
+                        for mut start in starts.iter_mut() {
+                            start.lose_counter = 0.;
+                        }
+                    }
+                    ShopItem::Ferry => {
+                        shop.ferries_bought += 1;
+                        shop.ferries += 1;
+                    }
+                    ShopItem::FerryUpgrade => shop.ferry_level += 1,
+                }
+
+                // Show the new prices
+                for entity in panel.iter() {
+                    cmd.entity(entity).despawn_recursive();
+                }
+                if let Ok(node) = node.get_single() {
+                    layout_shop(&mut cmd, node, &style, &shop);
+                }
+            }
+            Interaction::Hovered => {
+                bg.0 = opts.color.mid;
+                text.sections[0].style.color = opts.color.dark;
+            }
+            Interaction::None => {
+                bg.0 = opts.color.light;
+                text.sections[0].style.color = opts.color.dark;
+            }
+        }
+    }
+}
+
+fn tick_slowdown(time: Res<Time>, mut shop: ResMut<Shop>) {
+    let finished = shop
+        .slowdown
+        .as_mut()
+        .is_some_and(|timer| timer.tick(time.delta()).finished());
+    if finished {
+        shop.slowdown = None;
+    }
+}
+
+// Leaving the run or letting the bot play closes the shop without buying anything else
+fn close_shop(
+    mut cmd: Commands,
+    mut time: ResMut<Time<Virtual>>,
+    panel: Query<Entity, With<ShopPanel>>,
+) {
+    for entity in panel.iter() {
+        cmd.entity(entity).despawn_recursive();
+    }
+    cmd.remove_resource::<ShopOpen>();
+    time.unpause();
+}
+
+// ·····
+// Extra
+// ·····
+
+fn layout_shop(cmd: &mut Commands, node: Entity, style: &UIStyle, shop: &Shop) {
+    let Some(mut node) = cmd.get_entity(node) else {
+        return;
+    };
+    node.with_children(|parent| {
+        parent
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.),
+                        height: Val::Percent(100.),
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        row_gap: Val::Px(12.),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                    ..default()
+                },
+                ShopPanel,
+                UI_LAYER,
+            ))
+            .with_children(|panel| {
+                UIText::simple(style, "The ferryman's stall")
+                    .with_title()
+                    .add(panel);
+                UIText::simple(style, &format!("You have {} obols", shop.obols)).add(panel);
+
+                for item in SHOP_ITEMS {
+                    UIOption::new(style, item.name()).add(panel, |row| {
+                        match item.cost(shop) {
+                            Some(cost) if cost <= shop.obols => UIButton::new(
+                                style,
+                                &format!("{} obols", cost),
+                                Some(ShopButton::Buy(item)),
+                            ),
+                            Some(cost) => UIButton::new(style, &format!("{} obols", cost), None),
+                            None => UIButton::new(style, "Sold out", None),
+                        }
+                        .with_width(Val::Px(100.))
+                        .add(row);
+                    });
+                }
+
+                UIButton::new(style, "Continue", Some(ShopButton::Close)).add(panel);
+            });
+    });
+}
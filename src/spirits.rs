@@ -1,6 +1,7 @@
+#![allow(clippy::too_many_arguments)]
 #![allow(clippy::type_complexity)]
 
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use bevy::prelude::*;
 use bevy_ecs_tilemap::prelude::*;
@@ -8,16 +9,24 @@ use bevy_persistent::Persistent;
 use rand::Rng;
 
 use crate::{
+    animation::Animator,
+    aseprite::Aseprite,
     config::GameScore,
+    game::GameMode,
     load::{SpiritAssets, StartAssets},
+    modifiers::RunModifiers,
+    shop::Shop,
     tilemap::{
-        get_neighbours, pos_to_tile, tile_to_pos, EndTile, PathTile, StartTile, TilemapLayer,
+        can_flow, get_neighbours, pos_to_tile, tile_to_pos, Affinity, EndTile, Lane, PathTile,
+        StartTile, TilemapLayer,
     },
     GameState,
 };
 
 const SPIRIT_SPEED: f32 = 200.;
 const SPIRIT_SIZE: f32 = 50.;
+// Dot over spirits tinted by their affinity that shows the color of their kind
+const KIND_BADGE_SIZE: f32 = 16.;
 const MAX_SPIRITS_IN_TILE: u32 = 3;
 
 pub const INITIAL_SPAWN_TIME: f32 = 1.2;
@@ -25,6 +34,16 @@ const LOSE_COUNT: f32 = 30.;
 
 const FUN_A: f32 = 10.;
 
+// Spirits that become available as the score grows (score needed, kind, spawn weight)
+const SPAWN_TABLE: [(u32, SpiritKind, u32); 5] = [
+    (0, SpiritKind::Normal, 12),
+    (40, SpiritKind::Swift, 3),
+    (120, SpiritKind::Heavy, 2),
+    (250, SpiritKind::Impatient, 2),
+    (400, SpiritKind::Valuable, 1),
+];
+const IMPATIENT_TIME: f32 = 25.;
+
 // ······
 // Plugin
 // ······
@@ -34,11 +53,13 @@ pub struct SpiritPlugin;
 impl Plugin for SpiritPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(EndTimer::default())
+            .add_event::<SpiritArrived>()
             .add_systems(
                 Update,
                 (
                     spawn_spirit,
                     check_lose_count,
+                    check_patience,
                     next_tile_spirit,
                     spirit_collision,
                     move_spirit,
@@ -60,7 +81,7 @@ impl Plugin for SpiritPlugin {
 // ·········
 
 #[derive(Resource)]
-pub struct EndTimer(Timer);
+pub struct EndTimer(pub Timer);
 
 impl Default for EndTimer {
     fn default() -> Self {
@@ -68,33 +89,148 @@ impl Default for EndTimer {
     }
 }
 
+// ······
+// Events
+// ······
+
+// Sent when spirits are delivered to an end, so it can play its animation
+#[derive(Event)]
+pub struct SpiritArrived {
+    pub end: TilePos,
+}
+
 // ··········
 // Components
 // ··········
 
 #[derive(Component)]
 pub struct Spirit {
+    start_tile: TilePos,
     prev_tile: Option<TilePos>,
     curr_tile: TilePos,
     curr_distance: f32,
     next_tile: Option<TilePos>,
     next_pos: Vec2,
     selected_end: Option<TilePos>,
+    // Lane used to cross the bridge it is on
+    lane: Option<Lane>,
     vel: Vec2,
-    animate_timer: Timer,
+    // Time it can spend blocked before giving up
+    patience: Option<Timer>,
 }
 
 impl Spirit {
-    pub fn new(curr_tile: TilePos, curr_pos: Vec2) -> Self {
+    pub fn new(curr_tile: TilePos, curr_pos: Vec2, kind: SpiritKind) -> Self {
         Self {
+            start_tile: curr_tile,
             prev_tile: Some(curr_tile),
             curr_tile,
             curr_distance: std::f32::MAX,
             next_tile: None,
             next_pos: curr_pos,
             selected_end: None,
+            lane: None,
             vel: Vec2::ZERO,
-            animate_timer: Timer::from_seconds(0.5, TimerMode::Repeating),
+            patience: kind
+                .patience()
+                .map(|time| Timer::from_seconds(time, TimerMode::Once)),
+        }
+    }
+
+    pub fn tile(&self) -> TilePos {
+        self.curr_tile
+    }
+
+    // The tile where it is standing, if it is not moving to another one
+    pub fn waiting_on(&self) -> Option<TilePos> {
+        self.next_tile.is_none().then_some(self.curr_tile)
+    }
+
+    // The tile where this spirit is counted (the one it is moving to, if any)
+    fn occupied_tile(&self) -> TilePos {
+        self.next_tile.unwrap_or(self.curr_tile)
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpiritKind {
+    Normal,
+    Swift,
+    Heavy,
+    Impatient,
+    Valuable,
+}
+
+impl SpiritKind {
+    // Choose a random kind from the ones unlocked for this score
+    pub fn random(score: u32, rng: &mut impl Rng) -> Self {
+        let unlocked = SPAWN_TABLE.iter().filter(|(min, _, _)| score >= *min);
+        let total: u32 = unlocked.clone().map(|(_, _, weight)| weight).sum();
+
+        let mut r = rng.gen_range(0..total.max(1));
+        for (_, kind, weight) in unlocked {
+            if r < *weight {
+                return *kind;
+            }
+            r -= weight;
+        }
+        SpiritKind::Normal
+    }
+
+    pub fn speed(&self) -> f32 {
+        match self {
+            SpiritKind::Swift => SPIRIT_SPEED * 1.6,
+            SpiritKind::Heavy => SPIRIT_SPEED * 0.6,
+            SpiritKind::Impatient => SPIRIT_SPEED * 1.2,
+            _ => SPIRIT_SPEED,
+        }
+    }
+
+    // How many places it takes towards MAX_SPIRITS_IN_TILE
+    pub fn weight(&self) -> u32 {
+        match self {
+            SpiritKind::Heavy => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        match self {
+            SpiritKind::Heavy => 2,
+            SpiritKind::Valuable => 5,
+            _ => 1,
+        }
+    }
+
+    // Seconds it can wait blocked, then it is lost and counts against its start
+    pub fn patience(&self) -> Option<f32> {
+        match self {
+            SpiritKind::Impatient => Some(IMPATIENT_TIME),
+            _ => None,
+        }
+    }
+
+    // Tag of the sprites of this kind, the animation clips are prefixed with it
+    fn tag(&self) -> &'static str {
+        match self {
+            SpiritKind::Normal | SpiritKind::Valuable => "normal",
+            SpiritKind::Swift | SpiritKind::Impatient => "swift",
+            SpiritKind::Heavy => "heavy",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            SpiritKind::Impatient => Color::rgb(1.0, 0.6, 0.7),
+            SpiritKind::Valuable => Color::rgb(1.0, 0.9, 0.4),
+            _ => Color::WHITE,
+        }
+    }
+
+    fn scale(&self) -> f32 {
+        match self {
+            SpiritKind::Heavy => 0.9,
+            _ => 0.75,
         }
     }
 }
@@ -118,12 +254,16 @@ fn reset_spirits(
 fn spawn_spirit(
     mut cmd: Commands,
     time: Res<Time>,
+    score: Res<Persistent<GameScore>>,
+    shop: Res<Shop>,
     spirit_assets: Res<SpiritAssets>,
-    mut start: Query<(&TilePos, &mut StartTile, &mut PathTile)>,
+    sprites: Res<Assets<Aseprite>>,
+    mut start: Query<(&TilePos, &mut StartTile, &mut PathTile, Option<&Affinity>)>,
     tilemap: Query<(&TilemapLayer, &TilemapGridSize, &TilemapType, &Transform)>,
 ) {
-    for (start_pos, mut start_tile, mut start_path) in start.iter_mut() {
-        if start_tile.spawn_timer.tick(time.delta()).just_finished() {
+    for (start_pos, mut start_tile, mut start_path, affinity) in start.iter_mut() {
+        let delta = time.delta().mul_f32(shop.spawn_rate());
+        if start_tile.spawn_timer.tick(delta).just_finished() {
             start_tile.lose_counter += 1.;
 
             for (layer, grid_size, map_type, trans) in tilemap.iter() {
@@ -140,23 +280,29 @@ fn spawn_spirit(
                 // Calculate the spawn position
                 let pos = tile_to_pos(start_pos, grid_size, map_type, trans);
 
+                // Kinds don't use the run seed, how many are drawn depends on the frame timing
+                let kind = SpiritKind::random(score.delivered, &mut rand::thread_rng());
+
                 // If there is already another entity there, don't spawn
                 if start_path.count >= 2 {
                     continue;
                 }
-                start_path.count += 1;
+                let Some(sprite) = sprites.get(&spirit_assets.stix) else {
+                    continue;
+                };
+                start_path.count += kind.weight();
 
                 // Spawn the entity at the start of the path
-                cmd.spawn((
-                    SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new((rand::random::<usize>() % 3) * 2),
-                        texture_atlas: spirit_assets.stix.clone(),
-                        transform: Transform::from_translation(pos.extend(5.))
-                            .with_scale(Vec3::splat(0.75)),
-                        ..default()
-                    },
-                    Spirit::new(*start_pos, pos),
-                ));
+                spawn_spirit_at(
+                    &mut cmd,
+                    sprite,
+                    &spirit_assets,
+                    *start_pos,
+                    *start_pos,
+                    pos,
+                    kind,
+                    affinity,
+                );
                 start_tile.lose_counter = (start_tile.lose_counter - 2.).max(0.);
 
                 // Reduce timer 0.01 seconds until it is 0.5
@@ -176,10 +322,16 @@ fn check_lose_count(
     mut state: ResMut<NextState<GameState>>,
     assets: Res<StartAssets>,
     end_timer: Res<EndTimer>,
+    mode: Res<GameMode>,
     mut start: Query<(&TilePos, &mut TileColor, &mut StartTile)>,
     mut text: Query<&mut Text, With<LoseText>>,
     tilemap: Query<(&TilemapLayer, &TilemapGridSize, &TilemapType, &Transform)>,
 ) {
+    // Nothing is lost in zen runs
+    if *mode == GameMode::Zen {
+        return;
+    }
+
     for (pos, mut color, mut start) in start.iter_mut() {
         let lose_text = start.lose_text;
 
@@ -242,12 +394,57 @@ fn check_lose_count(
     }
 }
 
+fn check_patience(
+    mut cmd: Commands,
+    time: Res<Time>,
+    mut spirits: Query<(Entity, &mut Spirit, &SpiritKind, Option<&Affinity>)>,
+    mut paths: Query<(&TilePos, &mut PathTile)>,
+    mut start: Query<(&TilePos, &mut StartTile)>,
+    end: Query<(&TilePos, Option<&Affinity>), With<EndTile>>,
+) {
+    for (entity, mut spirit, kind, affinity) in spirits.iter_mut() {
+        // It only loses patience while it is blocked, not while it waits its turn at an end
+        let arrived = end.iter().any(|(pos, end_affinity)| {
+            *pos == spirit.curr_tile && Affinity::matches(affinity, end_affinity)
+        });
+        if spirit.next_tile.is_some() || arrived {
+            continue;
+        }
+        let Some(patience) = spirit.patience.as_mut() else {
+            continue;
+        };
+        if !patience.tick(time.delta()).just_finished() {
+            continue;
+        }
+
+        // The spirit gives up, free its place and count it against its start
+        let occupied = spirit.occupied_tile();
+        for (pos, mut path) in paths.iter_mut() {
+            if *pos == occupied {
+                path.count = path.count.saturating_sub(kind.weight());
+            }
+        }
+        for (pos, mut start) in start.iter_mut() {
+            if *pos == spirit.start_tile {
+                start.lose_counter += 2.;
+            }
+        }
+        cmd.entity(entity).despawn_recursive();
+    }
+}
+
 fn next_tile_spirit(
     mut cmd: Commands,
-    mut spirit: Query<(Entity, &Transform, &mut Spirit)>,
+    mut spirit: Query<(
+        Entity,
+        &Transform,
+        &mut Spirit,
+        &SpiritKind,
+        Option<&Affinity>,
+    )>,
     mut paths: Query<(&TilePos, &mut PathTile)>,
     start: Query<Entity, With<StartTile>>,
-    end: Query<Entity, With<EndTile>>,
+    end: Query<(&TilePos, Option<&Affinity>), With<EndTile>>,
     tilemap: Query<
         (
             &TilemapLayer,
@@ -260,12 +457,24 @@ fn next_tile_spirit(
         Without<Spirit>,
     >,
 ) {
+    // Affinity of each end, used to only pick ends that accept the spirit
+    let end_affinities = end
+        .iter()
+        .map(|(pos, affinity)| (*pos, affinity.copied()))
+        .collect::<HashMap<_, _>>();
+
     for (layer, map_size, grid_size, map_type, storage, map_trans) in tilemap.iter() {
         match layer {
             TilemapLayer::RiverStix => {}
             _ => continue,
         }
-        for (spirit_entity, trans, mut spirit) in spirit.iter_mut() {
+        for (spirit_entity, trans, mut spirit, kind, affinity) in spirit.iter_mut() {
+            let accepts = |end: &TilePos| {
+                end_affinities
+                    .get(end)
+                    .map_or(false, |end| Affinity::matches(affinity, end.as_ref()))
+            };
+
             if let Some(tile_pos) = pos_to_tile(
                 &trans.translation.xy(),
                 map_size,
@@ -298,27 +507,41 @@ fn next_tile_spirit(
                 // Get the next tile
                 if spirit.next_tile.is_none() || spirit.next_tile.unwrap() == tile_pos {
                     spirit.next_tile = None;
-                    // If the spirit is on the end tile, despawn
+                    // If the spirit is on an end tile that accepts it, despawn
                     if let Some(entity) = storage.get(&tile_pos) {
-                        if end.get(entity).is_ok() {
-                            continue;
+                        if let Ok((end_pos, _)) = end.get(entity) {
+                            if accepts(end_pos) {
+                                continue;
+                            }
                         }
                         if let Some(end) = spirit.selected_end {
                             if let Ok((_, path)) = paths.get(entity) {
-                                spirit.curr_distance =
-                                    *path.distance.get(&end).unwrap_or(&spirit.curr_distance);
+                                spirit.curr_distance = path
+                                    .distance_to(&end, spirit.lane)
+                                    .unwrap_or(spirit.curr_distance);
                             }
                         }
                     }
 
-                    // Get the possible next tiles (they must be paths)
-                    let neighbour_list = get_neighbours(&tile_pos, map_size);
+                    // Get the possible next tiles (they must be paths that don't go against the current)
+                    // On a bridge, spirits keep going in the lane they entered on
+                    let curr_lane = spirit.lane;
+                    let curr_current = storage
+                        .get(&tile_pos)
+                        .and_then(|entity| paths.get(entity).ok())
+                        .and_then(|(_, path)| path.current);
+                    let neighbour_list = get_neighbours(&tile_pos, map_size, map_type);
                     let neighbours = neighbour_list
                         .iter()
                         .filter_map(|pos| storage.get(pos))
                         .filter_map(|entity| {
                             if let Ok((pos, path)) = paths.get(entity) {
-                                if start.get(entity).is_ok() {
+                                if start.get(entity).is_ok()
+                                    || !can_flow(&tile_pos, curr_current, pos, path.current)
+                                    || curr_lane.is_some_and(|lane| {
+                                        lane != Lane::between(&tile_pos, pos, map_type)
+                                    })
+                                {
                                     return None;
                                 }
                                 Some((pos, path))
@@ -360,21 +583,22 @@ fn next_tile_spirit(
 
                             // Get the end position
                             // If there is no selected end, calculate the closest one
+                            let lane = path
+                                .bridge
+                                .then_some(Lane::between(&tile_pos, pos, map_type));
                             let (end, dist) = if let Some(end) = spirit.selected_end {
-                                (
-                                    spirit.selected_end.as_ref().unwrap(),
-                                    path.distance.get(&end).unwrap_or(&std::f32::MAX),
-                                )
+                                (end, path.distance_to(&end, lane).unwrap_or(std::f32::MAX))
                             } else {
-                                path.distance
-                                    .iter()
+                                path.distances(lane)
+                                    .into_iter()
+                                    .filter(|(end, _)| accepts(end))
                                     .min_by(|(_, a), (_, b)| min_dist(a, b))
-                                    .unwrap_or((&tile_pos, &std::f32::MAX))
+                                    .unwrap_or((tile_pos, std::f32::MAX))
                             };
 
                             // If the selected end is different from the current one, reset the distance
                             if let Some(s_end) = spirit.selected_end {
-                                if s_end != *end {
+                                if s_end != end {
                                     reset_distance = true;
                                 }
                             }
@@ -382,9 +606,13 @@ fn next_tile_spirit(
                             // Add a random offset to the distance
                             let r = rand::thread_rng().gen_range(0.0..0.1);
 
-                            (*pos, *dist + r, Some(*end), path.count)
+                            // Deeper rivers have room for more spirits
+                            let room =
+                                (MAX_SPIRITS_IN_TILE + path.depth).saturating_sub(path.count);
+
+                            (*pos, dist + r, Some(end), room, lane)
                         })
-                        .filter(|(pos, dist, _, count)| {
+                        .filter(|(pos, dist, _, room, _)| {
                             let is_start = if let Some(entity) = storage.get(pos) {
                                 start.get(entity).is_ok()
                             } else {
@@ -396,9 +624,9 @@ fn next_tile_spirit(
                                 false
                             };
                             let is_further = *dist < spirit.curr_distance;
-                            *count < MAX_SPIRITS_IN_TILE && is_further && !is_start && !is_prev
+                            kind.weight() <= *room && is_further && !is_start && !is_prev
                         })
-                        .min_by(|(_, a, _, _), (_, b, _, _)| {
+                        .min_by(|(_, a, _, _, _), (_, b, _, _, _)| {
                             a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
                         });
 
@@ -417,16 +645,17 @@ fn next_tile_spirit(
                     spirit.next_pos =
                         tile_to_pos(&spirit.next_tile.unwrap(), grid_size, map_type, map_trans);
                     spirit.selected_end = next.unwrap().2;
+                    spirit.lane = next.unwrap().4;
 
                     // Update counts
                     if let Some(entity) = storage.get(&spirit.next_tile.unwrap()) {
                         if let Ok((_, mut path)) = paths.get_mut(entity) {
-                            path.count += 1;
+                            path.count += kind.weight();
                         }
                     }
                     if let Some(entity) = storage.get(&spirit.prev_tile.unwrap()) {
                         if let Ok((_, mut path)) = paths.get_mut(entity) {
-                            path.count = path.count.saturating_sub(1);
+                            path.count = path.count.saturating_sub(kind.weight());
                         }
                     }
                 }
@@ -439,33 +668,40 @@ fn clear_end_count(
     mut cmd: Commands,
     time: Res<Time>,
     mut score: ResMut<Persistent<GameScore>>,
-    mut end: Query<(&mut PathTile, &TilePos), With<EndTile>>,
-    spirits: Query<(Entity, &Spirit)>,
+    mut modifiers: ResMut<RunModifiers>,
+    mut shop: ResMut<Shop>,
+    mut end: Query<(&mut PathTile, &TilePos, Option<&Affinity>), With<EndTile>>,
+    spirits: Query<(Entity, &Spirit, &SpiritKind, Option<&Affinity>)>,
     mut timer: ResMut<EndTimer>,
+    mut arrivals: EventWriter<SpiritArrived>,
 ) {
     if !timer.0.tick(time.delta()).just_finished() {
         return;
     }
-    for (mut end, end_pos) in end.iter_mut() {
-        for (entity, spirit) in spirits.iter() {
-            if spirit.curr_tile == *end_pos {
+    for (mut end, end_pos, end_affinity) in end.iter_mut() {
+        for (entity, spirit, kind, affinity) in spirits.iter() {
+            if spirit.curr_tile == *end_pos && Affinity::matches(affinity, end_affinity) {
                 cmd.get_entity(entity).unwrap().despawn_recursive();
-                end.count = end.count.saturating_sub(1);
-                score.score += 1;
+                end.count = end.count.saturating_sub(kind.weight());
+                score.delivered += kind.score();
+                score.score += modifiers.award(kind.score());
+                shop.obols += kind.score();
+                arrivals.send(SpiritArrived { end: *end_pos });
                 break;
             }
         }
     }
 }
 
-fn move_spirit(mut spirits: Query<(&mut Spirit, &Transform)>) {
-    for (mut spirit, trans) in spirits.iter_mut() {
+fn move_spirit(shop: Res<Shop>, mut spirits: Query<(&mut Spirit, &SpiritKind, &Transform)>) {
+    for (mut spirit, kind, trans) in spirits.iter_mut() {
         // Move towards next tile
         let delta = spirit.next_pos - trans.translation.xy();
         let dir = delta.normalize_or_zero();
-        spirit.vel = spirit
-            .vel
-            .lerp(dir * SPIRIT_SPEED.min(delta.length_squared()), 0.1);
+        spirit.vel = spirit.vel.lerp(
+            dir * (kind.speed() * shop.speed()).min(delta.length_squared()),
+            0.1,
+        );
     }
 }
 
@@ -496,15 +732,71 @@ fn integrate(mut spirits: Query<(&Spirit, &mut Transform)>, time: Res<Time>) {
     }
 }
 
-fn animate_spirit(time: Res<Time>, mut spirits: Query<(&mut Spirit, &mut TextureAtlasSprite)>) {
-    for (mut spirit, mut tex) in spirits.iter_mut() {
-        if !spirit.animate_timer.tick(time.delta()).just_finished() {
-            continue;
-        }
-        tex.index = if tex.index % 2 == 0 {
-            tex.index + 1
+// Switch the animation to match what the spirit is doing
+fn animate_spirit(mut spirits: Query<(&Spirit, &SpiritKind, &mut Animator)>) {
+    for (spirit, kind, mut animator) in spirits.iter_mut() {
+        let state = if spirit.selected_end == Some(spirit.curr_tile) {
+            "arriving"
+        } else if spirit.next_tile.is_some() {
+            "moving"
+        } else if spirit.curr_distance < std::f32::MAX {
+            // It has somewhere to go but the way is full
+            "waiting"
         } else {
-            tex.index - 1
+            "idle"
         };
+        animator.play(&format!("{}_{}", kind.tag(), state));
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Spawn a spirit standing on a tile, it counts against its start if it gives up
+pub fn spawn_spirit_at(
+    cmd: &mut Commands,
+    sprite: &Aseprite,
+    assets: &SpiritAssets,
+    start: TilePos,
+    tile: TilePos,
+    pos: Vec2,
+    kind: SpiritKind,
+    affinity: Option<&Affinity>,
+) {
+    let mut spirit = Spirit::new(tile, pos, kind);
+    spirit.start_tile = start;
+
+    let mut spirit = cmd.spawn((
+        SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                index: sprite.clip(kind.tag()).frames.first().copied().unwrap_or(0),
+                color: affinity.map_or(kind.color(), |a| a.color()),
+                ..default()
+            },
+            texture_atlas: sprite.atlas.clone(),
+            transform: Transform::from_translation(pos.extend(5.))
+                .with_scale(Vec3::splat(kind.scale())),
+            ..default()
+        },
+        spirit,
+        kind,
+        Animator::new(assets.stix_animations.clone(), "normal_idle"),
+    ));
+    if let Some(affinity) = affinity {
+        spirit.insert(*affinity);
+        if kind.color() != Color::WHITE {
+            spirit.with_children(|spirit| {
+                spirit.spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: kind.color(),
+                        custom_size: Some(Vec2::splat(KIND_BADGE_SIZE)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0., SPIRIT_SIZE, 0.1),
+                    ..default()
+                });
+            });
+        }
     }
 }
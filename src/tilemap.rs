@@ -7,21 +7,47 @@ use std::{
 };
 
 use bevy::prelude::*;
-use bevy_ecs_tilemap::{helpers::square_grid::neighbors::SquareDirection, prelude::*};
+use bevy_ecs_tilemap::{
+    helpers::{
+        hex_grid::{axial::AxialPos, neighbors::HEX_DIRECTIONS},
+        square_grid::neighbors::SquareDirection,
+    },
+    prelude::*,
+};
 use bevy_persistent::Persistent;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Keybinds,
+    animation::Animator,
+    config::{GameOptions, Keybinds},
+    erosion::Erosion,
+    fog::Fog,
+    game::GameMode,
     input::{Bind, MousePosition},
+    level::{CurrentLevel, Level},
     load::TilemapAssets,
-    spirits::INITIAL_SPAWN_TIME,
+    shop::ShopOpen,
+    spirits::{SpiritArrived, INITIAL_SPAWN_TIME},
+    tileset::{pick_tileset, Tileset, TilesetGrid, TilesetLoader},
     GameState,
 };
 
-pub const MAP_SIZE: TilemapSize = TilemapSize { x: 24, y: 19 };
-const TILE_SIZE: TilemapTileSize = TilemapTileSize { x: 128., y: 128. };
-const GRID_SIZE: TilemapGridSize = TilemapGridSize { x: 127.5, y: 127.5 };
+// Tiles are only spawned in chunks near the level, so the map can be very big
+pub const MAP_SIZE: TilemapSize = TilemapSize { x: 256, y: 256 };
+const CHUNK_SIZE: TilemapSize = TilemapSize { x: 8, y: 8 };
+// Tilesets are scaled so their tiles have this size in the world
+const TILE_SIZE: f32 = 128.;
 const INITIAL_TILES: u32 = 9;
+// Seconds before a spirit spawns when the start tile begins its animation
+const SPAWN_ANTICIPATION: f32 = 0.4;
+// Tints for rivers that can't reach any end (and starts with no way out), and for dead ends
+pub const DISCONNECTED_COLOR: Color = Color::rgb(0.9, 0.4, 0.6);
+const DEAD_END_COLOR: Color = Color::rgb(1.0, 0.85, 0.6);
+const HINT_COLOR: Color = Color::rgba(0.8, 1.0, 0.9, 0.45);
+const FOG_COLOR: Color = Color::rgb(0.3, 0.25, 0.4);
+// Rivers about to silt up, and the ones that got deeper (by depth)
+const SILT_COLOR: Color = Color::rgb(0.75, 0.65, 0.45);
+const DEEP_COLORS: [Color; 2] = [Color::rgb(0.7, 0.85, 1.0), Color::rgb(0.5, 0.65, 1.0)];
 
 // ······
 // Plugin
@@ -33,23 +59,55 @@ impl Plugin for TilePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(NeedsReset)
             .add_plugins(TilemapPlugin)
+            .init_asset::<Tileset>()
+            .init_asset_loader::<TilesetLoader>()
             .add_systems(
                 OnEnter(GameState::Play),
                 init_tilemap.run_if(resource_added::<NeedsReset>()),
             )
+            .add_systems(
+                OnEnter(GameState::Editor),
+                init_tilemap.run_if(resource_added::<NeedsReset>()),
+            )
             .add_systems(
                 Update,
-                (select_tile, click_tile).run_if(in_state(GameState::Play)),
+                (
+                    spawn_chunks.run_if(
+                        resource_exists_and_changed::<LevelSize>().and_then(
+                            in_state(GameState::Play).or_else(in_state(GameState::Editor)),
+                        ),
+                    ),
+                    select_tile
+                        .run_if(in_state(GameState::Play).or_else(in_state(GameState::Editor))),
+                    // The shop pauses the game, so nothing can be built while it is open
+                    (click_tile, rotate_current, toggle_bridge).run_if(
+                        in_state(GameState::Play).and_then(not(resource_exists::<ShopOpen>())),
+                    ),
+                ),
             )
             .add_systems(
                 PostUpdate,
                 (
-                    highlight_tile,
-                    (autotile, pathfinding).run_if(resource_exists_and_changed::<TilesAvailable>()),
-                )
-                    .run_if(in_state(GameState::Play)),
+                    (animate_tiles, highlight_tile).chain().run_if(
+                        resource_exists::<ActiveTileset>().and_then(
+                            in_state(GameState::Play).or_else(in_state(GameState::Editor)),
+                        ),
+                    ),
+                    (
+                        autotile,
+                        show_currents,
+                        pathfinding,
+                        count_disconnected.after(pathfinding),
+                        show_flow
+                            .after(pathfinding)
+                            .run_if(resource_exists::<ActiveTileset>()),
+                    )
+                        .run_if(in_state(GameState::Play))
+                        .run_if(resource_exists_and_changed::<TilesAvailable>()),
+                ),
             )
-            .add_systems(OnEnter(GameState::End), reset_tilemap);
+            .add_systems(OnEnter(GameState::End), reset_tilemap)
+            .add_systems(OnExit(GameState::Editor), reset_tilemap);
     }
 }
 
@@ -61,7 +119,7 @@ impl Plugin for TilePlugin {
 pub struct TilesAvailable(pub u32);
 
 #[derive(Resource)]
-pub struct SelectedPos(Option<TilePos>);
+pub struct SelectedPos(pub Option<TilePos>);
 
 #[derive(Resource)]
 pub struct LevelSize(pub TilemapSize);
@@ -69,6 +127,17 @@ pub struct LevelSize(pub TilemapSize);
 #[derive(Resource)]
 pub struct NeedsReset;
 
+// Textures of the tileset used by the current tilemap
+#[derive(Resource)]
+pub struct ActiveTileset(pub TilesetGrid);
+
+// River tiles and starts that are not connected to any end
+#[derive(Resource, Default)]
+pub struct Disconnected {
+    pub tiles: u32,
+    pub starts: u32,
+}
+
 // ··········
 // Components
 // ··········
@@ -79,6 +148,8 @@ pub struct SelectedTile;
 #[derive(Component)]
 pub struct StartTile {
     pub completed_once: bool,
+    // If it has a path to an end right now
+    pub connected: bool,
     pub spawn_timer: Timer,
     pub lose_counter: f32,
     pub lose_text: Option<Entity>,
@@ -88,6 +159,7 @@ impl Default for StartTile {
     fn default() -> Self {
         Self {
             completed_once: false,
+            connected: false,
             spawn_timer: Timer::from_seconds(INITIAL_SPAWN_TIME, TimerMode::Repeating),
             lose_counter: 0.,
             lose_text: None,
@@ -98,22 +170,85 @@ impl Default for StartTile {
 #[derive(Component)]
 pub struct EndTile;
 
-#[derive(Clone)]
-pub enum PathShape {
-    None,
-    End,
-    Straight,
-    Turn,
-    Junction,
-    Crossing,
+// Ground of a tile, placed on the background and river layers
+// Tiles without terrain are normal ground
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Terrain {
+    Rock,
+    Marsh,
+    River,
+    Bridge,
+}
+
+impl Terrain {
+    // Tiles needed to build a path here, none if it can't be built on
+    // Existing rivers and their bridges are already there, so they are free
+    pub fn cost(terrain: Option<&Terrain>) -> Option<u32> {
+        match terrain {
+            None => Some(1),
+            Some(Terrain::Rock) => None,
+            Some(Terrain::Marsh) => Some(2),
+            Some(Terrain::River) | Some(Terrain::Bridge) => Some(0),
+        }
+    }
+
+    // Distance added when going through this tile
+    pub fn weight(terrain: Option<&Terrain>) -> f32 {
+        match terrain {
+            Some(Terrain::Marsh) => 2.,
+            Some(Terrain::River) => 0.5,
+            _ => 1.,
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Terrain::Rock => Color::rgb(0.35, 0.3, 0.4),
+            Terrain::Marsh => Color::rgb(0.45, 0.6, 0.45),
+            Terrain::River => Color::rgb(0.5, 0.65, 0.95),
+            Terrain::Bridge => Color::rgb(0.7, 0.55, 0.4),
+        }
+    }
+}
+
+// Color of a start, end or spirit when playing with color matching
+// Spirits can only be delivered to an end of their same affinity
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Affinity(pub usize);
+
+pub const AFFINITY_COLORS: [Color; 3] = [
+    Color::rgb(0.6, 1.0, 0.8),
+    Color::rgb(1.0, 0.7, 0.5),
+    Color::rgb(0.7, 0.6, 1.0),
+];
+
+impl Affinity {
+    pub fn color(&self) -> Color {
+        AFFINITY_COLORS[self.0 % AFFINITY_COLORS.len()]
+    }
+
+    // Tiles or spirits without affinity match everything
+    pub fn matches(a: Option<&Affinity>, b: Option<&Affinity>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
 }
 
 #[derive(Component, Clone)]
 pub struct PathTile {
     pub distance: HashMap<TilePos, f32>,
     pub count: u32,
-    pub shape: PathShape,
-    pub rot: u32,
+    // Connected neighbours, bit `i` is set for the direction index `i`
+    pub mask: u32,
+    // One way tiles only let spirits leave towards this neighbour
+    pub current: Option<TilePos>,
+    // Bridges keep the horizontal and vertical flows apart
+    pub bridge: bool,
+    pub lane_distance: HashMap<(TilePos, Lane), f32>,
+    // Extra spirits that fit in it, rivers deepen when they are used a lot
+    pub depth: u32,
 }
 
 impl Default for PathTile {
@@ -121,23 +256,80 @@ impl Default for PathTile {
         Self {
             distance: HashMap::new(),
             count: 0,
-            shape: PathShape::End,
-            rot: 0,
+            mask: 0,
+            current: None,
+            bridge: false,
+            lane_distance: HashMap::new(),
+            depth: 0,
         }
     }
 }
 
+impl PathTile {
+    // Distance to an end, on bridges it depends on the lane used to cross it
+    pub fn distance_to(&self, end: &TilePos, lane: Option<Lane>) -> Option<f32> {
+        match lane {
+            Some(lane) if self.bridge => self.lane_distance.get(&(*end, lane)).copied(),
+            _ => self.distance.get(end).copied(),
+        }
+    }
+
+    // Distances to all the ends from the given lane
+    pub fn distances(&self, lane: Option<Lane>) -> Vec<(TilePos, f32)> {
+        match lane {
+            Some(lane) if self.bridge => self
+                .lane_distance
+                .iter()
+                .filter(|((_, l), _)| *l == lane)
+                .map(|((end, _), dist)| (*end, *dist))
+                .collect(),
+            _ => self
+                .distance
+                .iter()
+                .map(|(end, dist)| (*end, *dist))
+                .collect(),
+        }
+    }
+
+    // If any end can be reached from this tile
+    pub fn reaches_end(&self) -> bool {
+        self.distance.values().any(|dist| dist.is_finite())
+    }
+
+    fn set_distance(&mut self, end: TilePos, lane: Option<Lane>, dist: f32) {
+        if let Some(lane) = lane {
+            self.lane_distance.insert((end, lane), dist);
+        }
+        let best = self.distance.entry(end).or_insert(dist);
+        *best = best.min(dist);
+    }
+}
+
+// Axis used to go through a bridge (two on square grids, three on hex grids)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Lane(usize);
+
+impl Lane {
+    pub fn between(a: &TilePos, b: &TilePos, map_type: &TilemapType) -> Self {
+        let axes = if is_hex(map_type) { 3 } else { 2 };
+        Lane(direction_index(a, b, map_type).map_or(0, |i| i % axes))
+    }
+}
+
 #[derive(Component, Clone, Copy)]
 pub enum TilemapLayer {
     Background,
     RiverStix,
     RiverPhlege,
+    // Ripples that show where the river goes
+    Flow,
     Foreground,
 }
-const TILEMAP_LAYERS: [TilemapLayer; 4] = [
+const TILEMAP_LAYERS: [TilemapLayer; 5] = [
     TilemapLayer::Background,
     TilemapLayer::RiverStix,
     TilemapLayer::RiverPhlege,
+    TilemapLayer::Flow,
     TilemapLayer::Foreground,
 ];
 
@@ -145,46 +337,122 @@ const TILEMAP_LAYERS: [TilemapLayer; 4] = [
 pub enum ForegroundTile {
     Start,
     End,
+    Decoration(u32),
+    // Direction index of the current
+    Current(usize),
+    // Ghost of a suggested river, with its connected directions
+    Hint(u32),
 }
 
 // ·······
 // Systems
 // ·······
 
-fn init_tilemap(mut cmd: Commands, tile_assets: Res<TilemapAssets>) {
+fn init_tilemap(
+    mut cmd: Commands,
+    tile_assets: Res<TilemapAssets>,
+    tilesets: Res<Assets<Tileset>>,
+    level: Option<Res<CurrentLevel>>,
+    levels: Res<Assets<Level>>,
+    opts: Res<Persistent<GameOptions>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mode: Res<GameMode>,
+) {
+    // Hex grids are only used for endless runs, hand made levels and the daily challenge are square
+    let hex = opts.hex_grid
+        && level.is_none()
+        && *mode != GameMode::Daily
+        && *state.get() == GameState::Play;
+
+    // Use a square grid if no tileset has hex tiles
+    let tiles = pick_tileset(&opts.tileset, hex, &tile_assets.tilesets, &tilesets);
+    let hex = hex && tiles.is_some();
+    let tiles =
+        tiles.or_else(|| pick_tileset(&opts.tileset, false, &tile_assets.tilesets, &tilesets));
+    let Some(tiles) = tiles else {
+        // There is nothing to play on, go back instead of showing an empty board
+        error!("There is no tileset for this grid");
+        next_state.set(GameState::Menu);
+        return;
+    };
+    let map_type = if hex {
+        TilemapType::Hexagon(HexCoordSystem::RowOdd)
+    } else {
+        TilemapType::default()
+    };
+    let tile_size = TilemapTileSize {
+        x: tiles.tile_size.0,
+        y: tiles.tile_size.1,
+    };
+    let grid_size = TilemapGridSize {
+        x: tiles.grid_size.0,
+        y: tiles.grid_size.1,
+    };
+    let scale = TILE_SIZE / tile_size.x;
+
+    // Hand made levels have their own size and tile budget
+    let (size, available) = match level.and_then(|level| levels.get(&level.0)) {
+        Some(level) => (
+            TilemapSize {
+                x: level.size.0.min(MAP_SIZE.x),
+                y: level.size.1.min(MAP_SIZE.y),
+            },
+            level.tiles,
+        ),
+        None => (TilemapSize { x: 8, y: 3 }, INITIAL_TILES),
+    };
+
     for (i, &layer) in TILEMAP_LAYERS.iter().enumerate() {
         let tilemap = cmd.spawn_empty().id();
 
-        // Spawn tiles
+        // Spawn the tiles around the initial level, the rest are added as it grows
         let mut storage = TileStorage::empty(MAP_SIZE);
-        fill_tilemap(
+        fill_chunks(
             TilemapId(tilemap),
-            MAP_SIZE,
+            &LevelSize(size),
             &mut storage,
             TileVisible(i == 0),
             &mut cmd,
         );
 
+        // The flow ripples have their own image
+        let texture = match (layer, &tiles.flow) {
+            (TilemapLayer::Flow, Some(flow)) => flow.texture.clone(),
+            _ => tiles.texture.clone(),
+        };
+
+        // Smaller tilesets are scaled up, keeping the map centered
+        let mut transform =
+            get_tilemap_center_transform(&MAP_SIZE, &grid_size, &map_type, i as f32);
+        transform.translation *= Vec3::new(scale, scale, 1.);
+        transform.scale = Vec3::new(scale, scale, 1.);
+
         // Create tilemap
-        let map_type = TilemapType::default();
         cmd.entity(tilemap).insert((
             TilemapBundle {
                 size: MAP_SIZE,
-                tile_size: TILE_SIZE,
-                grid_size: GRID_SIZE,
+                tile_size,
+                grid_size,
                 map_type,
                 storage,
-                texture: TilemapTexture::Single(tile_assets.stix.clone()),
-                transform: get_tilemap_center_transform(&MAP_SIZE, &GRID_SIZE, &map_type, i as f32),
+                texture: TilemapTexture::Single(texture),
+                transform,
+                render_settings: TilemapRenderSettings {
+                    render_chunk_size: UVec2::new(CHUNK_SIZE.x, CHUNK_SIZE.y),
+                    ..default()
+                },
                 ..default()
             },
             layer,
         ));
     }
 
-    cmd.insert_resource(TilesAvailable(INITIAL_TILES));
+    cmd.insert_resource(ActiveTileset(tiles.clone()));
+    cmd.insert_resource(Disconnected::default());
+    cmd.insert_resource(TilesAvailable(available));
     cmd.insert_resource(SelectedPos(None));
-    cmd.insert_resource(LevelSize(TilemapSize { x: 8, y: 3 }));
+    cmd.insert_resource(LevelSize(size));
     cmd.remove_resource::<NeedsReset>();
 }
 
@@ -200,6 +468,23 @@ fn reset_tilemap(
     cmd.insert_resource(NeedsReset);
 }
 
+// Spawn the chunks that the level grew into
+fn spawn_chunks(
+    mut cmd: Commands,
+    level_size: Res<LevelSize>,
+    mut tilemap: Query<(Entity, &TilemapLayer, &mut TileStorage)>,
+) {
+    for (entity, layer, mut storage) in tilemap.iter_mut() {
+        fill_chunks(
+            TilemapId(entity),
+            &level_size,
+            &mut storage,
+            TileVisible(matches!(layer, TilemapLayer::Background)),
+            &mut cmd,
+        );
+    }
+}
+
 fn select_tile(
     mut cmd: Commands,
     mouse: Res<MousePosition>,
@@ -243,7 +528,12 @@ fn select_tile(
 fn click_tile(
     mut cmd: Commands,
     mut selected: Query<(Entity, &mut TileVisible), With<SelectedTile>>,
-    tiles: Query<(Option<&PathTile>, Option<&StartTile>, Option<&EndTile>)>,
+    tiles: Query<(
+        Option<&PathTile>,
+        Option<&StartTile>,
+        Option<&EndTile>,
+        Option<&Terrain>,
+    )>,
     input: Res<Input<Bind>>,
     keybinds: Res<Persistent<Keybinds>>,
     mut available: ResMut<TilesAvailable>,
@@ -259,7 +549,7 @@ fn click_tile(
 
     if select {
         if let Ok((entity, mut visible)) = selected.get_single_mut() {
-            if let Ok((path, start, end)) = tiles.get(entity) {
+            if let Ok((path, start, end, terrain)) = tiles.get(entity) {
                 if prev.is_none() {
                     *prev = Some(path.is_some());
                 }
@@ -270,21 +560,34 @@ fn click_tile(
                 }
 
                 if start.is_none() && end.is_none() {
-                    // Erase path
-                    if path.is_some() {
-                        cmd.entity(entity).remove::<PathTile>();
+                    // The terrain decides how many tiles it takes
+                    let Some(cost) = Terrain::cost(terrain) else {
+                        return;
+                    };
+
+                    let natural_bridge = terrain == Some(&Terrain::Bridge);
+
+                    // Erase path (built bridges give back their extra tile)
+                    if let Some(path) = path {
+                        cmd.entity(entity).remove::<(PathTile, Erosion)>();
                         visible.0 = false;
-                        available.0 += 1;
+                        available.0 += cost;
+                        if path.bridge && !natural_bridge {
+                            available.0 += 1;
+                        }
                         return;
                     }
 
                     // Add paths
-                    if available.0 == 0 {
+                    if available.0 < cost {
                         return;
                     }
-                    cmd.entity(entity).insert(PathTile::default());
+                    cmd.entity(entity).insert(PathTile {
+                        bridge: natural_bridge,
+                        ..default()
+                    });
                     visible.0 = true;
-                    available.0 -= 1;
+                    available.0 -= cost;
                 }
             }
             return;
@@ -293,29 +596,163 @@ fn click_tile(
     *prev = None;
 }
 
+// Cycle the direction of the current of the selected path
+fn rotate_current(
+    selected: Query<(Entity, &TilePos), With<SelectedTile>>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType)>,
+    mut paths: Query<&mut PathTile, (Without<StartTile>, Without<EndTile>)>,
+    input: Res<Input<Bind>>,
+    keybinds: Res<Persistent<Keybinds>>,
+    mut available: ResMut<TilesAvailable>,
+) {
+    if !keybinds
+        .rotate_current
+        .iter()
+        .any(|bind| input.just_pressed(*bind))
+    {
+        return;
+    }
+
+    let Some((_, size, map_type)) = tilemap
+        .iter()
+        .find(|(layer, _, _)| matches!(layer, TilemapLayer::RiverStix))
+    else {
+        return;
+    };
+
+    if let Ok((entity, pos)) = selected.get_single() {
+        if let Ok(mut path) = paths.get_mut(entity) {
+            // Go through every neighbour and then back to no current
+            let neighbours = get_neighbours(pos, size, map_type);
+            path.current = match path.current {
+                None => neighbours.first().copied(),
+                Some(current) => neighbours
+                    .iter()
+                    .position(|n| *n == current)
+                    .and_then(|i| neighbours.get(i + 1).copied()),
+            };
+            // Recalculate the paths
+            available.set_changed();
+        }
+    }
+}
+
+// Turn the selected path into a bridge or back, building one takes an extra tile
+fn toggle_bridge(
+    selected: Query<Entity, With<SelectedTile>>,
+    mut paths: Query<(&mut PathTile, Option<&Terrain>), (Without<StartTile>, Without<EndTile>)>,
+    input: Res<Input<Bind>>,
+    keybinds: Res<Persistent<Keybinds>>,
+    mut available: ResMut<TilesAvailable>,
+) {
+    if !keybinds
+        .toggle_bridge
+        .iter()
+        .any(|bind| input.just_pressed(*bind))
+    {
+        return;
+    }
+
+    if let Ok(entity) = selected.get_single() {
+        if let Ok((mut path, terrain)) = paths.get_mut(entity) {
+            // Bridges on the terrain are always there
+            if terrain == Some(&Terrain::Bridge) {
+                return;
+            }
+
+            if path.bridge {
+                path.bridge = false;
+                available.0 += 1;
+            } else if available.0 > 0 {
+                path.bridge = true;
+                available.0 -= 1;
+            }
+        }
+    }
+}
+
 fn highlight_tile(
-    mut tiles: Query<(
-        &mut TileTextureIndex,
-        &mut TileColor,
-        &mut TileFlip,
-        &TilePos,
-        Option<&PathTile>,
-        Option<&StartTile>,
-        Option<&ForegroundTile>,
-    )>,
+    mut tiles: Query<
+        (
+            &mut TileTextureIndex,
+            &mut TileColor,
+            &mut TileFlip,
+            &TilePos,
+            Option<&PathTile>,
+            Option<&StartTile>,
+            Option<&ForegroundTile>,
+            Option<&Affinity>,
+            Option<&Terrain>,
+            Option<&Animator>,
+            Option<&Erosion>,
+        ),
+        Without<AnimatedTile>,
+    >,
     level_size: Res<LevelSize>,
     sel_pos: Res<SelectedPos>,
     end_tiles: Query<(&TilePos, With<EndTile>)>,
+    start_tiles: Query<(&TilePos, &StartTile)>,
+    tileset: Res<ActiveTileset>,
+    state: Res<State<GameState>>,
+    fog: Option<Res<Fog>>,
 ) {
+    let tileset = &tileset.0;
+    // The editor doesn't search for paths, so there is nothing to warn about
+    let warn = *state.get() == GameState::Play;
+
     let mut ends = Vec::new();
     for (pos, _) in end_tiles.iter() {
         ends.push(*pos);
     }
+    let unconnected = start_tiles
+        .iter()
+        .filter(|(_, start)| !start.connected)
+        .map(|(pos, _)| *pos)
+        .collect::<Vec<_>>();
+
+    for (
+        mut tex,
+        mut color,
+        mut flip,
+        pos,
+        path,
+        start,
+        foreground,
+        affinity,
+        terrain,
+        animator,
+        erosion,
+    ) in tiles.iter_mut()
+    {
+        let animated = |index: u32| {
+            animator
+                .and_then(|animator| animator.index())
+                .map_or(index, |index| index as u32)
+        };
+
+        // Tiles under the fog don't show what is on them, but they can still be selected
+        if fog.as_ref().is_some_and(|fog| !fog.revealed.contains(pos))
+            && tile_in_level(pos, &level_size)
+        {
+            *tex = TileTextureIndex(if sel_pos.0 == Some(*pos) {
+                tileset.selected
+            } else {
+                tileset.background
+            });
+            *color = TileColor(FOG_COLOR);
+            *flip = TileFlip::default();
+            continue;
+        }
+
+        if let Some(Terrain::Rock) = terrain {
+            *tex = TileTextureIndex(tileset.background);
+            *color = TileColor(Terrain::Rock.color());
+            continue;
+        }
 
-    for (mut tex, mut color, mut flip, pos, path, start, foreground) in tiles.iter_mut() {
         if let Some(sel_pos) = sel_pos.0 {
             if sel_pos == *pos && path.is_none() && foreground.is_none() {
-                *tex = TileTextureIndex(1);
+                *tex = TileTextureIndex(tileset.selected);
                 continue;
             }
         }
@@ -326,65 +763,149 @@ fn highlight_tile(
         }
 
         if start.is_some() {
-            *tex = TileTextureIndex(9);
+            *tex = TileTextureIndex(tileset.start_river);
             continue;
         }
 
         *color = TileColor::default();
 
         if let Some(fg) = foreground {
+            if let Some(affinity) = affinity {
+                *color = TileColor(affinity.color());
+            }
+            *flip = TileFlip::default();
             match fg {
                 ForegroundTile::Start => {
-                    *tex = TileTextureIndex(12);
+                    *tex = TileTextureIndex(animated(tileset.start));
+                    if warn && unconnected.contains(pos) {
+                        *color = TileColor(DISCONNECTED_COLOR);
+                    }
                 }
                 ForegroundTile::End => {
-                    *tex = TileTextureIndex(11);
+                    *tex = TileTextureIndex(animated(tileset.end));
+                }
+                ForegroundTile::Decoration(index) => {
+                    tileset.decoration(*index).apply(&mut tex, &mut flip);
+                }
+                ForegroundTile::Current(dir) => {
+                    tileset.arrow(*dir).apply(&mut tex, &mut flip);
+                }
+                ForegroundTile::Hint(mask) => {
+                    tileset.river(*mask).apply(&mut tex, &mut flip);
+                    *color = TileColor(HINT_COLOR);
                 }
             }
             continue;
         }
 
-        if path.is_some() {
-            *tex = match path.unwrap().shape {
-                PathShape::None => TileTextureIndex(0),
-                PathShape::End => TileTextureIndex(4),
-                PathShape::Straight => TileTextureIndex(2),
-                PathShape::Turn => TileTextureIndex(6),
-                PathShape::Junction => TileTextureIndex(8),
-                PathShape::Crossing => TileTextureIndex(9),
-            };
-            *flip = flip_from_rotation(path.unwrap().rot);
+        if let Some(PathTile { bridge: true, .. }) = path {
+            *tex = TileTextureIndex(tileset.bridge);
+            *flip = TileFlip::default();
+            *color = TileColor(Terrain::Bridge.color());
+        } else if let Some(path) = path {
+            tileset.river(path.mask).apply(&mut tex, &mut flip);
+            if warn && !path.reaches_end() {
+                *color = TileColor(DISCONNECTED_COLOR);
+            } else if warn && path.mask.count_ones() <= 1 && !ends.contains(pos) {
+                *color = TileColor(DEAD_END_COLOR);
+            } else if erosion.is_some_and(|erosion| erosion.silting()) {
+                *color = TileColor(SILT_COLOR);
+            } else if path.depth > 0 {
+                *color =
+                    TileColor(DEEP_COLORS[(path.depth as usize - 1).min(DEEP_COLORS.len() - 1)]);
+            }
         } else {
-            *tex = TileTextureIndex(0);
+            *tex = TileTextureIndex(tileset.background);
+            if let Some(terrain) = terrain {
+                *color = TileColor(terrain.color());
+            }
+        }
+    }
+}
+
+// Animate the start tiles before they spawn a spirit and the ends when one arrives
+fn animate_tiles(
+    mut cmd: Commands,
+    tileset: Res<ActiveTileset>,
+    mut arrivals: EventReader<SpiritArrived>,
+    starts: Query<(&TilePos, &StartTile)>,
+    mut foreground: Query<(Entity, &TilePos, &ForegroundTile, Option<&mut Animator>)>,
+) {
+    let Some(set) = &tileset.0.animation_set else {
+        return;
+    };
+
+    let arrived = arrivals
+        .read()
+        .map(|arrival| arrival.end)
+        .collect::<Vec<_>>();
+    let spawning = starts
+        .iter()
+        .filter(|(_, start)| start.spawn_timer.remaining_secs() < SPAWN_ANTICIPATION)
+        .map(|(pos, _)| *pos)
+        .collect::<Vec<_>>();
+
+    for (entity, pos, fg, animator) in foreground.iter_mut() {
+        let clip = match fg {
+            ForegroundTile::Start if spawning.contains(pos) => "start_spawning",
+            ForegroundTile::Start => "start_idle",
+            ForegroundTile::End if arrived.contains(pos) => "end_arriving",
+            // Let the arrival finish before going back to idle
+            ForegroundTile::End => match &animator {
+                Some(animator) if animator.clip() == "end_arriving" && !animator.finished() => {
+                    "end_arriving"
+                }
+                _ => "end_idle",
+            },
+            _ => continue,
+        };
+
+        match animator {
+            Some(mut animator) => animator.play(clip),
+            None => {
+                cmd.entity(entity).insert(Animator::new(set.clone(), clip));
+            }
         }
     }
 }
 
 fn pathfinding(
-    tilemap: Query<(&TilemapLayer, &TilemapSize, &TileStorage)>,
-    mut start: Query<(&TilePos, &mut StartTile)>,
-    end: Query<&TilePos, With<EndTile>>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType, &TileStorage)>,
+    mut start: Query<(&TilePos, &mut StartTile, Option<&Affinity>)>,
+    end: Query<(&TilePos, Option<&Affinity>), With<EndTile>>,
     mut paths: Query<(&TilePos, &mut PathTile)>,
+    terrain: Query<&Terrain>,
 ) {
     // Clear all paths
     for (_, mut path) in paths.iter_mut() {
         path.distance.clear();
+        path.lane_distance.clear();
+    }
+    for (_, mut start_tile, _) in start.iter_mut() {
+        start_tile.connected = false;
     }
 
-    for (layer, size, storage) in tilemap.iter() {
+    let currents = paths
+        .iter()
+        .filter_map(|(pos, path)| path.current.map(|current| (*pos, current)))
+        .collect::<HashMap<_, _>>();
+
+    for (layer, size, map_type, storage) in tilemap.iter() {
         match layer {
             TilemapLayer::RiverStix => {}
             _ => continue,
         }
 
-        for end_pos in end.iter() {
+        for (end_pos, end_affinity) in end.iter() {
             let mut open = BinaryHeap::new();
             let mut distances = HashMap::new();
 
             // Add the end position to the queue
-            distances.insert(*end_pos, 0.);
+            // Nodes are a position and the lane if it is a bridge
+            distances.insert((*end_pos, None), 0.);
             open.push(PathfindingNode {
                 pos: *end_pos,
+                lane: None,
                 distance: 0.,
             });
             if let Some(entity) = storage.get(end_pos) {
@@ -394,7 +915,12 @@ fn pathfinding(
             }
 
             // Start iterating through the queue
-            while let Some(PathfindingNode { pos, distance }) = open.pop() {
+            while let Some(PathfindingNode {
+                pos,
+                lane,
+                distance,
+            }) = open.pop()
+            {
                 // If the path is a start, cut this branch
                 if let Some(entity) = storage.get(&pos) {
                     if start.get_mut(entity).is_ok() {
@@ -403,20 +929,38 @@ fn pathfinding(
                 }
 
                 // Get the neighbouring tiles
-                let neighbours = get_neighbours(&pos, size);
+                let neighbours = get_neighbours(&pos, size, map_type);
 
                 for neighbour in neighbours {
+                    // The search goes backwards, so spirits would move from the neighbour to here
+                    if !can_flow(
+                        &neighbour,
+                        currents.get(&neighbour).copied(),
+                        &pos,
+                        currents.get(&pos).copied(),
+                    ) {
+                        continue;
+                    }
+
+                    // Bridges can only be crossed straight through
+                    let neighbour_lane = Lane::between(&neighbour, &pos, map_type);
+                    if lane.is_some_and(|lane| lane != neighbour_lane) {
+                        continue;
+                    }
+
                     if let Some(entity) = storage.get(&neighbour) {
                         if let Ok((_, mut path)) = paths.get_mut(entity) {
                             // Djikstra's algorithm to find the shortest path from each tile
-                            let dist = distance + 1.;
-                            if dist < *distances.get(&neighbour).unwrap_or(&std::f32::INFINITY) {
-                                distances.insert(neighbour, dist);
+                            let node = (neighbour, path.bridge.then_some(neighbour_lane));
+                            let dist = distance + Terrain::weight(terrain.get(entity).ok());
+                            if dist < *distances.get(&node).unwrap_or(&std::f32::INFINITY) {
+                                distances.insert(node, dist);
                                 open.push(PathfindingNode {
                                     pos: neighbour,
+                                    lane: node.1,
                                     distance: dist,
                                 });
-                                path.distance.insert(*end_pos, dist);
+                                path.set_distance(*end_pos, node.1, dist);
                             }
                         }
                     }
@@ -431,9 +975,13 @@ fn pathfinding(
             }
 
             // Check if there is a path from the end to the start
-            for (start_pos, mut start_tile) in start.iter_mut() {
-                if distances.contains_key(start_pos) {
+            // With color matching, only ends with the same affinity count
+            for (start_pos, mut start_tile, start_affinity) in start.iter_mut() {
+                if distances.contains_key(&(*start_pos, None))
+                    && Affinity::matches(start_affinity, end_affinity)
+                {
                     start_tile.completed_once = true;
+                    start_tile.connected = true;
                     // Set begin distance to MAX
                     if let Some(entity) = storage.get(start_pos) {
                         if let Ok((_, mut path)) = paths.get_mut(entity) {
@@ -447,105 +995,172 @@ fn pathfinding(
 }
 
 fn autotile(
-    tilemap: Query<(&TilemapLayer, &TilemapSize, &TileStorage)>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType, &TileStorage)>,
     mut paths: Query<(&TilePos, &mut PathTile)>,
 ) {
-    for (layer, size, storage) in tilemap.iter() {
+    for (layer, size, map_type, storage) in tilemap.iter() {
         match layer {
             TilemapLayer::RiverStix => {}
             _ => continue,
         }
 
-        let mut path_shapes = HashMap::new();
+        // The tileset picks the texture for each combination of connected directions
+        let masks = paths
+            .iter()
+            .map(|(pos, _)| {
+                let mask = get_neighbours(pos, size, map_type)
+                    .iter()
+                    .filter(|n| {
+                        storage
+                            .get(n)
+                            .map_or(false, |entity| paths.contains(entity))
+                    })
+                    .filter_map(|n| direction_index(pos, n, map_type))
+                    .fold(0, |mask, i| mask | 1 << i);
+                (*pos, mask)
+            })
+            .collect::<HashMap<_, _>>();
+
+        for (pos, mut path) in paths.iter_mut() {
+            if let Some(mask) = masks.get(pos) {
+                path.mask = *mask;
+            }
+        }
+    }
+}
+
+// Show the flow arrows of one way tiles on the foreground
+fn show_currents(
+    mut cmd: Commands,
+    tilemap: Query<(&TilemapLayer, &TilemapType, &TileStorage)>,
+    paths: Query<(&TilePos, &PathTile)>,
+    foreground: Query<(Entity, &ForegroundTile)>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    let currents = paths
+        .iter()
+        .filter_map(|(pos, path)| path.current.map(|current| (*pos, current)))
+        .collect::<HashMap<_, _>>();
 
-        for (pos, _) in paths.iter() {
-            let neighbours = get_neighbours(pos, size);
+    for (layer, map_type, storage) in tilemap.iter() {
+        match layer {
+            TilemapLayer::Foreground => {}
+            _ => continue,
+        }
 
-            // Get the neighbouring tiles
-            let neighbours = neighbours
-                .iter()
-                .filter_map(|pos| storage.get(pos))
-                .filter_map(|entity| {
-                    if let Ok((pos, _)) = paths.get(entity) {
-                        Some(pos)
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            // Get the shape of the path
-            let shape = match neighbours.len() {
-                1 => PathShape::End,
-                2 => {
-                    if neighbours[0].x == neighbours[1].x || neighbours[0].y == neighbours[1].y {
-                        PathShape::Straight
-                    } else {
-                        PathShape::Turn
-                    }
-                }
-                3 => PathShape::Junction,
-                4 => PathShape::Crossing,
-                _ => PathShape::None,
-            };
+        // Remove the arrows that are no longer needed
+        for (entity, fg) in foreground.iter() {
+            if !matches!(fg, ForegroundTile::Current(_)) {
+                continue;
+            }
+            cmd.entity(entity).remove::<ForegroundTile>();
+            if let Ok(mut visible) = visible.get_mut(entity) {
+                visible.0 = false;
+            }
+        }
 
-            // Get the rotation
-            let rot = match shape {
-                PathShape::End => {
-                    if neighbours.is_empty() || neighbours[0].x < pos.x {
-                        0
-                    } else if neighbours[0].x > pos.x {
-                        2
-                    } else if neighbours[0].y < pos.y {
-                        1
-                    } else {
-                        3
-                    }
-                }
-                PathShape::Straight => {
-                    if neighbours[0].x == neighbours[1].x {
-                        1
-                    } else {
-                        0
-                    }
+        for (pos, current) in currents.iter() {
+            let dir = direction_index(pos, current, map_type).unwrap_or(0);
+            if let Some(entity) = storage.get(pos) {
+                // Decorations and other foreground tiles are kept, only arrows are replaced
+                if foreground
+                    .get(entity)
+                    .is_ok_and(|(_, fg)| !matches!(fg, ForegroundTile::Current(_)))
+                {
+                    continue;
                 }
-                PathShape::Turn => {
-                    if neighbours[0].x < neighbours[1].x {
-                        if neighbours[0].y > neighbours[1].y {
-                            1
-                        } else {
-                            0
-                        }
-                    } else if neighbours[0].y > neighbours[1].y {
-                        2
-                    } else {
-                        3
-                    }
-                }
-                PathShape::Junction => {
-                    if neighbours[0].y == neighbours[1].y {
-                        if neighbours[0].y < neighbours[2].y {
-                            0
-                        } else {
-                            2
-                        }
-                    } else if neighbours[0].x < neighbours[2].x {
-                        1
-                    } else {
-                        3
-                    }
+                cmd.entity(entity).insert(ForegroundTile::Current(dir));
+                if let Ok(mut visible) = visible.get_mut(entity) {
+                    visible.0 = true;
                 }
-                _ => 0,
-            };
+            }
+        }
+    }
+}
+
+fn count_disconnected(
+    mut disconnected: ResMut<Disconnected>,
+    paths: Query<&PathTile, (Without<StartTile>, Without<EndTile>)>,
+    starts: Query<&StartTile>,
+) {
+    disconnected.tiles = paths.iter().filter(|path| !path.reaches_end()).count() as u32;
+    disconnected.starts = starts.iter().filter(|start| !start.connected).count() as u32;
+}
 
-            path_shapes.insert(*pos, (shape, rot));
+// Point the ripples of each river tile downstream, water that can't reach any end stays still
+fn show_flow(
+    mut cmd: Commands,
+    tileset: Res<ActiveTileset>,
+    tilemap: Query<(&TilemapLayer, &TilemapSize, &TilemapType, &TileStorage)>,
+    paths: Query<(&TilePos, &PathTile, Option<&StartTile>)>,
+    animated: Query<Entity, With<AnimatedTile>>,
+    mut visible: Query<&mut TileVisible>,
+) {
+    let Some(flow) = &tileset.0.flow else {
+        return;
+    };
+    let river = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::RiverStix));
+    let overlay = tilemap
+        .iter()
+        .find(|(layer, ..)| matches!(layer, TilemapLayer::Flow));
+    let (Some((_, size, map_type, river)), Some((.., overlay))) = (river, overlay) else {
+        return;
+    };
+
+    // Remove the previous ripples
+    for entity in animated.iter() {
+        cmd.entity(entity).remove::<AnimatedTile>();
+        if let Ok(mut visible) = visible.get_mut(entity) {
+            visible.0 = false;
         }
+    }
 
-        for (pos, mut path) in paths.iter_mut() {
-            let (shape, rot) = path_shapes.get(pos).unwrap();
+    // Distance to the closest end that can be reached
+    let best = |path: &PathTile| {
+        path.distance
+            .values()
+            .copied()
+            .filter(|dist| dist.is_finite())
+            .reduce(f32::min)
+    };
+
+    for (pos, path, _) in paths.iter() {
+        if path.bridge {
+            continue;
+        }
 
-            path.shape = shape.clone();
-            path.rot = *rot;
+        let row = match best(path) {
+            None => flow.still,
+            // Ends don't flow anywhere
+            Some(dist) if dist <= 0. => continue,
+            Some(dist) => {
+                // The neighbour that spirits would move to, going down the distances
+                let downstream = get_neighbours(pos, size, map_type)
+                    .iter()
+                    .filter_map(|n| river.get(n).and_then(|entity| paths.get(entity).ok()))
+                    .filter(|(n, next, start)| {
+                        start.is_none() && can_flow(pos, path.current, n, next.current)
+                    })
+                    .filter_map(|(n, next, _)| best(next).map(|d| (*n, d)))
+                    .filter(|(_, d)| *d < dist)
+                    .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                let Some(dir) = downstream.and_then(|(n, _)| direction_index(pos, &n, map_type))
+                else {
+                    continue;
+                };
+                dir as u32
+            }
+        };
+
+        if let Some(entity) = overlay.get(pos) {
+            let animation = flow.animation(row);
+            cmd.entity(entity)
+                .insert((TileTextureIndex(animation.start), animation));
+            if let Ok(mut visible) = visible.get_mut(entity) {
+                visible.0 = true;
+            }
         }
     }
 }
@@ -554,15 +1169,73 @@ fn autotile(
 // Extra
 // ·····
 
+// Spirits can only leave a one way tile in its direction, and can't enter one against it
+pub fn can_flow(
+    from: &TilePos,
+    from_current: Option<TilePos>,
+    to: &TilePos,
+    to_current: Option<TilePos>,
+) -> bool {
+    from_current.map_or(true, |current| current == *to)
+        && to_current.map_or(true, |current| current != *from)
+}
+
+pub fn is_hex(map_type: &TilemapType) -> bool {
+    matches!(map_type, TilemapType::Hexagon(_))
+}
+
+// Index of the direction from one tile to its neighbour, counter clockwise starting east
+// Opposite directions are always half a turn apart
+pub fn direction_index(from: &TilePos, to: &TilePos, map_type: &TilemapType) -> Option<usize> {
+    if let TilemapType::Hexagon(coord_sys) = map_type {
+        let from = AxialPos::from_tile_pos_given_coord_system(from, *coord_sys);
+        let to = AxialPos::from_tile_pos_given_coord_system(to, *coord_sys);
+        return HEX_DIRECTIONS
+            .iter()
+            .position(|dir| from.offset(*dir) == to);
+    }
+
+    match (to.x as i32 - from.x as i32, to.y as i32 - from.y as i32) {
+        (1, 0) => Some(0),
+        (0, 1) => Some(1),
+        (-1, 0) => Some(2),
+        (0, -1) => Some(3),
+        _ => None,
+    }
+}
+
+// Number of tiles between two positions
+pub fn tile_distance(a: &TilePos, b: &TilePos, map_type: &TilemapType) -> u32 {
+    if let TilemapType::Hexagon(coord_sys) = map_type {
+        let a = AxialPos::from_tile_pos_given_coord_system(a, *coord_sys);
+        let b = AxialPos::from_tile_pos_given_coord_system(b, *coord_sys);
+        let (dq, dr) = (a.q - b.q, a.r - b.r);
+        return ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as u32;
+    }
+
+    ((a.x as i32 - b.x as i32).abs() + (a.y as i32 - b.y as i32).abs()) as u32
+}
+
 const DIRECTIONS: [SquareDirection; 4] = [
-    // DONT CHANGE THE ORDER, BREAKS AUTOTILING
     SquareDirection::West,
     SquareDirection::East,
     SquareDirection::North,
     SquareDirection::South,
 ];
 
-pub fn get_neighbours(pos: &TilePos, size: &TilemapSize) -> Vec<TilePos> {
+pub fn get_neighbours(pos: &TilePos, size: &TilemapSize, map_type: &TilemapType) -> Vec<TilePos> {
+    if let TilemapType::Hexagon(coord_sys) = map_type {
+        let axial = AxialPos::from_tile_pos_given_coord_system(pos, *coord_sys);
+        return HEX_DIRECTIONS
+            .iter()
+            .filter_map(|dir| {
+                axial
+                    .offset(*dir)
+                    .as_tile_pos_given_coord_system_and_map_size(*coord_sys, size)
+            })
+            .collect();
+    }
+
     let mut neighbours = Vec::new();
 
     for direction in DIRECTIONS.iter() {
@@ -576,6 +1249,7 @@ pub fn get_neighbours(pos: &TilePos, size: &TilemapSize) -> Vec<TilePos> {
 
 struct PathfindingNode {
     pos: TilePos,
+    lane: Option<Lane>,
     distance: f32,
 }
 
@@ -668,16 +1342,92 @@ pub fn tile_in_level(pos: &TilePos, level_size: &LevelSize) -> bool {
         && pos.y < offset.y + level_size.0.y
 }
 
-pub fn fill_tilemap(
+// Turn the tiles at this position into a start (if given) or an end
+// Call it for each tilemap layer, only the river and foreground are modified
+pub fn insert_start_end(
+    cmd: &mut Commands,
+    pos: &TilePos,
+    start: Option<StartTile>,
+    affinity: Option<Affinity>,
+    layer: &TilemapLayer,
+    storage: &TileStorage,
+    visible: &mut Query<&mut TileVisible>,
+) {
+    let Some(entity) = storage.get(pos) else {
+        return;
+    };
+    let is_start = start.is_some();
+
+    match layer {
+        // Insert the logical tile in the river
+        TilemapLayer::RiverStix => {
+            if let Some(start) = start {
+                cmd.entity(entity).insert((start, PathTile::default()));
+            } else {
+                cmd.entity(entity).insert((EndTile, PathTile::default()));
+            }
+        }
+        // Add the graphics element to the foreground
+        TilemapLayer::Foreground => {
+            cmd.entity(entity).insert(if is_start {
+                ForegroundTile::Start
+            } else {
+                ForegroundTile::End
+            });
+        }
+        _ => return,
+    }
+
+    if let Some(affinity) = affinity {
+        cmd.entity(entity).insert(affinity);
+    }
+    if let Ok(mut visible) = visible.get_mut(entity) {
+        visible.0 = true;
+    }
+}
+
+// Spawn every missing chunk that is in the level or next to it
+// The extra chunk around the level is enough for it to grow before the next update
+pub fn fill_chunks(
+    tilemap_id: TilemapId,
+    level_size: &LevelSize,
+    storage: &mut TileStorage,
+    visible: TileVisible,
+    commands: &mut Commands,
+) {
+    let (offset, size) = play_to_real_size(level_size);
+    let first = TilePos {
+        x: offset.x.saturating_sub(CHUNK_SIZE.x) / CHUNK_SIZE.x,
+        y: offset.y.saturating_sub(CHUNK_SIZE.y) / CHUNK_SIZE.y,
+    };
+    let last = TilePos {
+        x: ((offset.x + size.x + CHUNK_SIZE.x).min(MAP_SIZE.x) - 1) / CHUNK_SIZE.x,
+        y: ((offset.y + size.y + CHUNK_SIZE.y).min(MAP_SIZE.y) - 1) / CHUNK_SIZE.y,
+    };
+
+    for x in first.x..=last.x {
+        for y in first.y..=last.y {
+            let origin = TilePos {
+                x: x * CHUNK_SIZE.x,
+                y: y * CHUNK_SIZE.y,
+            };
+            if storage.get(&origin).is_none() {
+                fill_chunk(tilemap_id, &origin, storage, visible, commands);
+            }
+        }
+    }
+}
+
+fn fill_chunk(
     tilemap_id: TilemapId,
-    size: TilemapSize,
+    origin: &TilePos,
     storage: &mut TileStorage,
     visible: TileVisible,
     commands: &mut Commands,
 ) {
     commands.entity(tilemap_id.0).with_children(|parent| {
-        for x in 0..size.x {
-            for y in 0..size.y {
+        for x in origin.x..origin.x + CHUNK_SIZE.x {
+            for y in origin.y..origin.y + CHUNK_SIZE.y {
                 let tile_pos = TilePos { x, y };
                 let tile_entity = parent
                     .spawn(TileBundle {
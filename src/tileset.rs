@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+    reflect::TypePath,
+    tasks::futures_lite::AsyncReadExt,
+    utils::BoxedFuture,
+};
+use bevy_ecs_tilemap::prelude::*;
+use serde::Deserialize;
+
+use crate::{animation::AnimationSet, tilemap::flip_from_rotation};
+
+// ······
+// Assets
+// ······
+
+// Skin for the tilemap, loaded from a `.tileset.ron` file
+// Each grid is optional, missing ones are taken from the first tileset that has them
+#[derive(Asset, TypePath, Deserialize)]
+pub struct Tileset {
+    pub name: String,
+    #[serde(default)]
+    pub square: Option<TilesetGrid>,
+    #[serde(default)]
+    pub hex: Option<TilesetGrid>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TilesetGrid {
+    pub image: String,
+    #[serde(skip)]
+    pub texture: Handle<Image>,
+    // Size of each tile in the image and distance between tiles, both in pixels
+    pub tile_size: (f32, f32),
+    pub grid_size: (f32, f32),
+    pub background: u32,
+    pub selected: u32,
+    pub start: u32,
+    pub end: u32,
+    // Shown on the river layer below the start tiles
+    pub start_river: u32,
+    pub bridge: u32,
+    // One way arrows, ordered like the tile directions (counter clockwise starting east)
+    pub arrows: Vec<TileRule>,
+    // River texture for each mask of connected directions (bit `i` is direction `i`)
+    pub river: HashMap<u32, TileRule>,
+    // Replacements for the decoration textures used by the levels
+    #[serde(default)]
+    pub decorations: HashMap<u32, TileRule>,
+    // Clips for the start and end tiles, they are static without them
+    #[serde(default)]
+    pub animations: Option<String>,
+    #[serde(skip)]
+    pub animation_set: Option<Handle<AnimationSet>>,
+    #[serde(default)]
+    pub flow: Option<FlowTiles>,
+}
+
+// Animated ripples drawn over the river, with their own image
+// Each row of frames is a direction, and the `still` row is for water that can't reach an end
+#[derive(Deserialize, Clone)]
+pub struct FlowTiles {
+    pub image: String,
+    #[serde(skip)]
+    pub texture: Handle<Image>,
+    pub frames: u32,
+    pub still: u32,
+    pub speed: f32,
+}
+
+impl FlowTiles {
+    pub fn animation(&self, row: u32) -> AnimatedTile {
+        AnimatedTile {
+            start: row * self.frames,
+            end: (row + 1) * self.frames,
+            speed: self.speed,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+pub struct TileRule {
+    pub index: u32,
+    // Quarter turns, only makes sense on square grids
+    #[serde(default)]
+    pub rot: u32,
+    #[serde(default)]
+    pub flip_x: bool,
+    #[serde(default)]
+    pub flip_y: bool,
+}
+
+impl TileRule {
+    pub fn apply(&self, tex: &mut TileTextureIndex, flip: &mut TileFlip) {
+        tex.0 = self.index;
+        *flip = flip_from_rotation(self.rot);
+        flip.x ^= self.flip_x;
+        flip.y ^= self.flip_y;
+    }
+}
+
+impl TilesetGrid {
+    pub fn river(&self, mask: u32) -> TileRule {
+        self.river.get(&mask).copied().unwrap_or(TileRule {
+            index: self.background,
+            ..default()
+        })
+    }
+
+    pub fn arrow(&self, dir: usize) -> TileRule {
+        self.arrows.get(dir).copied().unwrap_or_default()
+    }
+
+    pub fn decoration(&self, texture: u32) -> TileRule {
+        self.decorations.get(&texture).copied().unwrap_or(TileRule {
+            index: texture,
+            ..default()
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct TilesetLoader;
+
+impl AssetLoader for TilesetLoader {
+    type Asset = Tileset;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Tileset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let mut tileset = ron::de::from_bytes::<Tileset>(&bytes)?;
+
+            // The images are loaded as dependencies of the tileset
+            for grid in [&mut tileset.square, &mut tileset.hex]
+                .into_iter()
+                .flatten()
+            {
+                grid.texture = load_context.load(grid.image.clone());
+                grid.animation_set = grid.animations.clone().map(|path| load_context.load(path));
+                if let Some(flow) = &mut grid.flow {
+                    flow.texture = load_context.load(flow.image.clone());
+                }
+            }
+            Ok(tileset)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tileset.ron"]
+    }
+}
+
+// ·····
+// Extra
+// ·····
+
+// Grid of the tileset chosen in the options, falling back to the first one that supports it
+pub fn pick_tileset<'a>(
+    name: &str,
+    hex: bool,
+    handles: &[Handle<Tileset>],
+    tilesets: &'a Assets<Tileset>,
+) -> Option<&'a TilesetGrid> {
+    let grid = |tileset: &'a Tileset| {
+        if hex {
+            tileset.hex.as_ref()
+        } else {
+            tileset.square.as_ref()
+        }
+    };
+
+    let loaded = handles
+        .iter()
+        .filter_map(|handle| tilesets.get(handle))
+        .collect::<Vec<_>>();
+
+    loaded
+        .iter()
+        .find(|tileset| tileset.name == name)
+        .and_then(|tileset| grid(tileset))
+        .or_else(|| loaded.iter().find_map(|tileset| grid(tileset)))
+}